@@ -2,9 +2,7 @@ use winapi::{
     shared::{
         minwindef::{DWORD, TRUE},
         ntdef::{HANDLE, NULL, ULONG, VOID},
-        winerror::ERROR_IO_PENDING,
-        ws2def::AF_INET6,
-        ws2ipdef::SOCKADDR_IN6,
+        winerror::{ERROR_IO_PENDING, WAIT_TIMEOUT},
     },
     um::{
         errhandlingapi::GetLastError,
@@ -29,16 +27,19 @@ use lazy_static::lazy_static;
 use static_assertions::assert_impl_all;
 
 use std::{
-    future::Future,
+    cell::{RefCell, UnsafeCell},
+    cmp::Ordering as CmpOrdering,
+    collections::{BinaryHeap, VecDeque},
+    future::{poll_fn, Future},
     marker::Unpin,
-    mem::replace,
-    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddrV6},
     pin::Pin,
-    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
-    sync::mpsc::{self, Receiver, SyncSender},
-    sync::{Arc, Mutex},
+    sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+    sync::mpsc::{self, Receiver, SyncSender, TrySendError},
+    sync::{Arc, Condvar, Mutex},
     task::{Context, Poll, Waker},
-    thread,
+    thread::{self, Thread},
+    time::{Duration, Instant},
 };
 
 /* For future reference:
@@ -58,14 +59,17 @@ use std::{
  * for all async pingers in that thread.
  *
  * State (which may be mutated by either calling thread or spawned thread) is stored in
- * an Arc<Mutex> to ensure (1) lack of data races, and (2) either thread dropping the
- * state does not invalidate the other.
+ * an Arc to ensure either thread dropping the state does not invalidate the other.
+ * It's an atomic word rather than a Mutex, as locking on every poll and completion
+ * was the bottleneck at high request rates.
  *
  */
 
 use crate::{
-    util::{windows_ipv4, windows_ipv6},
-    Buffer, Error, IpPair,
+    rate::Limiter,
+    rtt::Estimator,
+    util::{windows_ipv4, windows_sockaddr6},
+    AdaptiveTimeout, Buffer, Error, IpPair, RateLimit, RetryPolicy, RttEstimate,
 };
 /// A pinger that does not block when sending.
 #[derive(Clone)]
@@ -74,10 +78,12 @@ pub struct AsyncPinger {
     ttl: u8,
     df: bool,
     timeout: u32,
+    policies: Policies,
 }
 /// The result of an async ping. Contains a Result, and the
 /// buffer that was originally passed into the pinger.
 #[must_use]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct AsyncResult {
     #[must_use]
     pub result: Result<u32, Error>,
@@ -86,27 +92,86 @@ pub struct AsyncResult {
 /// The immediate return value of an AsyncPinger. You should probably just use
 /// async/await syntax instead.
 pub struct PingFuture {
-    state: Arc<Mutex<State>>,
+    shared: Arc<Shared>,
     kind: IpKind,
+    worker: Worker,
+    request: Request,
+    policies: Policies,
 }
 assert_impl_all!(PingFuture: Send, Unpin);
+/// The requests sent by [`AsyncPinger::send_batch`]. Results are returned in the order
+/// requests complete, along with the index of the request in the batch, by awaiting
+/// [`next`](Self::next) or blocking on [`wait_next`](Self::wait_next).
+pub struct PingBatch {
+    shared: Arc<BatchShared>,
+    remaining: usize,
+}
+assert_impl_all!(PingBatch: Send, Unpin);
+
+struct BatchShared {
+    completed: Mutex<BatchCompleted>,
+    done: Condvar,
+}
+#[derive(Default)]
+struct BatchCompleted {
+    results: VecDeque<(usize, AsyncResult)>,
+    waker: Option<Waker>,
+}
+
+// The state of a request, shared between its PingFuture and the worker thread.
+struct Shared {
+    // A State, encoded with State::encode
+    state: AtomicU64,
+    // Only used by the future. The buffer is put here before the request is sent, so
+    // it lives as long as the worker thread needs it, and taken once it completes.
+    buf: UnsafeCell<Option<Buffer>>,
+    notify: Registration,
+}
+// Safe as buf is only used by one thread at a time, see Shared::put_buffer
+unsafe impl Send for Shared {}
+unsafe impl Sync for Shared {}
 
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
 enum State {
-    Unpolled(Buffer),
-    Polled(Buffer, Waker),
-    Ready(Buffer),
-    Failed(Buffer, u32),
-    FailedAsyncSend(Buffer, u32),
-    Invalid,
+    Pending,
+    Ready,
+    Failed(u32),
+    FailedAsyncSend(u32),
+    Taken,
 }
 // Expected State Transitions
-// Initial state: Unpolled
-// Unpolled -> FailedAsyncSend if IcmpSend* returns unexpected value
-// Unpolled -> Failed if IcmpSend* returns error (other than IO_PENDING)
-// Unpolled -> Ready if not yet polled and callback_fn completes
-// Unpolled -> Polled if not yet polled when polled
-// Polled -> Polled if already polled when polled
-// Polled -> Ready if already polled and callback_fn completes
+// Initial state: Pending
+// Pending -> FailedAsyncSend if IcmpSend* returns unexpected value
+// Pending -> Failed if IcmpSend* returns error (other than IO_PENDING)
+// Pending -> Ready if callback_fn completes
+// Ready/Failed -> Pending if the request is retried
+// Ready/Failed -> Taken once the result is returned
+// Polling a Pending request registers a Notify, replacing any earlier one, and
+// completing the request takes the Notify and notifies it.
+
+// What to do when a polled request completes.
+enum Notify {
+    // The request is being waited on by a thread
+    Thread(Thread),
+    Waker(Waker),
+    // Finish the request on the worker thread, then call the callback
+    Callback(PingFuture, Callback),
+}
+type Callback = Box<dyn FnOnce(AsyncResult) + Send>;
+
+// A slot for the Notify of a pending request, in the style of futures' AtomicWaker.
+// The future registers a Notify while the worker thread may be taking it, without locking.
+struct Registration {
+    state: AtomicU8,
+    notify: UnsafeCell<Option<Notify>>,
+}
+const WAITING: u8 = 0;
+const REGISTERING: u8 = 1;
+const TAKING: u8 = 2;
+
+/// A function which runs completion callbacks, set with [`AsyncPinger::set_callback_dispatcher`].
+/// It's called on the worker thread, and may run the callback there or pass it to another thread.
+pub type Dispatcher = Arc<dyn Fn(Box<dyn FnOnce() + Send>) + Send + Sync>;
 
 impl AsyncPinger {
     /// Creates a new AsyncPinger.
@@ -126,6 +191,7 @@ impl AsyncPinger {
             ttl: 255,
             df: false,
             timeout: 2000,
+            policies: Policies::default(),
         }
     }
     /// Sets the IP TTL for future requets.
@@ -144,7 +210,8 @@ impl AsyncPinger {
     pub fn df(&self) -> bool {
         self.df
     }
-    /// Sets the timeout, in milliseconds, for future requests.
+    /// Sets the timeout, in milliseconds, for future requests. This is not used while
+    /// there is an adaptive timeout.
     pub fn set_timeout(&mut self, timeout: u32) {
         self.timeout = timeout;
     }
@@ -152,29 +219,102 @@ impl AsyncPinger {
     pub fn timeout(&self) -> u32 {
         self.timeout
     }
+    /// Sets the policy for retrying failed requests, or None to never retry. Defaults to None.
+    /// Retries happen while the returned future is being polled, and the number of attempts
+    /// taken is available from [`Buffer::attempts`].
+    pub fn set_retry_policy(&mut self, policy: Option<RetryPolicy>) {
+        self.policies.retry = policy.map(Arc::new);
+    }
+    /// Gets the current retry policy.
+    pub fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.policies.retry.as_deref()
+    }
+    /// Sets an adaptive timeout, which derives the timeout for each request from previous
+    /// round trip times to the same destination, or None to use the fixed timeout.
+    /// Defaults to None. Setting this discards any previous round trip times.
+    pub fn set_adaptive_timeout(&mut self, adaptive: Option<AdaptiveTimeout>) {
+        self.policies.rtt = adaptive.map(|settings| Arc::new(Estimator::new(settings)));
+    }
+    /// Gets the current adaptive timeout settings.
+    pub fn adaptive_timeout(&self) -> Option<&AdaptiveTimeout> {
+        self.policies.rtt.as_deref().map(Estimator::settings)
+    }
+    /// Gets the round trip time estimate for a destination, if there is an adaptive timeout
+    /// and there has been a reply or timeout from the destination.
+    pub fn rtt_estimate(&self, dst: IpAddr) -> Option<RttEstimate> {
+        self.policies.rtt.as_ref()?.estimate(dst)
+    }
+    /// Sets limits on how fast requests are sent, or None for no limits. Defaults to None.
+    /// Requests are held in the worker thread until the limits allow them, so sending
+    /// many requests at once doesn't need to be paced by the caller.
+    pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.policies.rate = limit.map(|limit| Arc::new(Limiter::new(limit)));
+    }
+    /// Gets the current rate limits.
+    pub fn rate_limit(&self) -> Option<&RateLimit> {
+        self.policies.rate.as_deref().map(Limiter::limit)
+    }
+    /// Sets the dispatcher which runs callbacks passed to [`send_with_callback`](Self::send_with_callback)
+    /// or [`PingFuture::on_complete`], or None to run them on the worker thread. Defaults to None.
+    pub fn set_callback_dispatcher(&mut self, dispatcher: Option<Dispatcher>) {
+        self.policies.dispatcher = dispatcher;
+    }
+    /// Gets the current callback dispatcher.
+    pub fn callback_dispatcher(&self) -> Option<&Dispatcher> {
+        self.policies.dispatcher.as_ref()
+    }
+    fn request(&self, pair: IpOptionalPair) -> Request {
+        Request {
+            pair,
+            ttl: self.ttl,
+            timeout: self.policies.timeout(pair.dst(), self.timeout),
+            df: self.df,
+        }
+    }
+    fn begin(&self, pair: IpOptionalPair, buf: Buffer) -> PingFuture {
+        let (future, job) = self
+            .worker
+            .prepare(self.request(pair), buf, self.policies.clone());
+        self.worker.submit(Some(job));
+        future
+    }
     /// Send an ICMPv4 request to the destination address. On success, returns the round trip time in milliseconds.
-    pub fn send4(&self, dst: Ipv4Addr, mut buf: Buffer) -> PingFuture {
-        buf.init_for_send();
-        self.worker
-            .begin_v4(None, dst, buf, self.ttl, self.timeout, self.df)
+    pub fn send4(&self, dst: Ipv4Addr, buf: Buffer) -> PingFuture {
+        self.begin(IpOptionalPair::V4 { src: None, dst }, buf)
     }
     /// Sends an ICMPv4 request from the source address to the destination address. On success, returns the round trip time in milliseconds.
-    pub fn send4_from(&self, src: Ipv4Addr, dst: Ipv4Addr, mut buf: Buffer) -> PingFuture {
-        buf.init_for_send();
-        self.worker
-            .begin_v4(Some(src), dst, buf, self.ttl, self.timeout, self.df)
+    pub fn send4_from(&self, src: Ipv4Addr, dst: Ipv4Addr, buf: Buffer) -> PingFuture {
+        let src = Some(src);
+        self.begin(IpOptionalPair::V4 { src, dst }, buf)
     }
     /// Sends an ICMPv6 request to the destination address. On success, returns the round trip time in milliseconds.
-    pub fn send6(&self, dst: Ipv6Addr, mut buf: Buffer) -> PingFuture {
-        buf.init_for_send();
-        self.worker
-            .begin_v6(None, dst, buf, self.ttl, self.timeout, self.df)
+    pub fn send6(&self, dst: Ipv6Addr, buf: Buffer) -> PingFuture {
+        self.send6_scoped(SocketAddrV6::new(dst, 0, 0, 0), buf)
     }
     /// Sends an ICMPv6 request from the source address to the destination address. On success, returns the round trip time in milliseconds.
-    pub fn send6_from(&self, src: Ipv6Addr, dst: Ipv6Addr, mut buf: Buffer) -> PingFuture {
-        buf.init_for_send();
-        self.worker
-            .begin_v6(Some(src), dst, buf, self.ttl, self.timeout, self.df)
+    pub fn send6_from(&self, src: Ipv6Addr, dst: Ipv6Addr, buf: Buffer) -> PingFuture {
+        self.send6_scoped_from(
+            SocketAddrV6::new(src, 0, 0, 0),
+            SocketAddrV6::new(dst, 0, 0, 0),
+            buf,
+        )
+    }
+    /// Sends an ICMPv6 request to the destination address, using its scope ID and flow info.
+    /// This is needed to reach link-local addresses on hosts with more than one interface.
+    /// The port is ignored. On success, returns the round trip time in milliseconds.
+    pub fn send6_scoped(&self, dst: SocketAddrV6, buf: Buffer) -> PingFuture {
+        self.begin(IpOptionalPair::V6 { src: None, dst }, buf)
+    }
+    /// Sends an ICMPv6 request from the source address to the destination address, using their
+    /// scope IDs and flow info. The ports are ignored. On success, returns the round trip time in milliseconds.
+    pub fn send6_scoped_from(
+        &self,
+        src: SocketAddrV6,
+        dst: SocketAddrV6,
+        buf: Buffer,
+    ) -> PingFuture {
+        let src = Some(src);
+        self.begin(IpOptionalPair::V6 { src, dst }, buf)
     }
     /// Sends an ICMP request to the destination address. Supports both v4 and v6. On success, returns the round trip time in milliseconds.
     pub fn send(&self, dst: IpAddr, buf: Buffer) -> PingFuture {
@@ -188,8 +328,52 @@ impl AsyncPinger {
         match src_dst_pair {
             IpPair::V4 { src, dst } => self.send4_from(src, dst, buf),
             IpPair::V6 { src, dst } => self.send6_from(src, dst, buf),
+            IpPair::V6Scoped { src, dst } => self.send6_scoped_from(src, dst, buf),
         }
     }
+    /// Sends an ICMP request to the destination address, and calls the callback with the
+    /// result once it completes (including any retries). This needs no executor or waiting
+    /// thread. See [`PingFuture::on_complete`] for where the callback runs.
+    pub fn send_with_callback<F>(&self, dst: IpAddr, buf: Buffer, callback: F)
+    where
+        F: FnOnce(AsyncResult) + Send + 'static,
+    {
+        self.send(dst, buf).on_complete(callback)
+    }
+    /// Sends an ICMP request to each destination address, with its buffer. This is
+    /// cheaper than calling [`send`](Self::send) for each request, as the worker thread
+    /// is woken once for the whole batch, rather than once per request.
+    pub fn send_batch<I>(&self, requests: I) -> PingBatch
+    where
+        I: IntoIterator<Item = (IpAddr, Buffer)>,
+    {
+        let shared = Arc::new(BatchShared {
+            completed: Mutex::new(BatchCompleted::default()),
+            done: Condvar::new(),
+        });
+        let mut policies = self.policies.clone();
+        // Completing a batch request only queues its result, so there's nothing to dispatch
+        policies.dispatcher = None;
+        let mut remaining = 0;
+        let jobs = requests.into_iter().map(|(dst, buf)| {
+            let pair = match dst {
+                IpAddr::V4(dst) => IpOptionalPair::V4 { src: None, dst },
+                IpAddr::V6(dst) => IpOptionalPair::V6 {
+                    src: None,
+                    dst: SocketAddrV6::new(dst, 0, 0, 0),
+                },
+            };
+            let (future, job) = self
+                .worker
+                .prepare(self.request(pair), buf, policies.clone());
+            let (index, shared) = (remaining, shared.clone());
+            future.on_complete(move |res| shared.push(index, res));
+            remaining += 1;
+            job
+        });
+        self.worker.submit(jobs);
+        PingBatch { shared, remaining }
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -199,106 +383,177 @@ enum IpOptionalPair {
         dst: Ipv4Addr,
     },
     V6 {
-        src: Option<Ipv6Addr>,
-        dst: Ipv6Addr,
+        src: Option<SocketAddrV6>,
+        dst: SocketAddrV6,
     },
 }
+impl IpOptionalPair {
+    fn dst(&self) -> IpAddr {
+        match *self {
+            IpOptionalPair::V4 { dst, .. } => dst.into(),
+            IpOptionalPair::V6 { dst, .. } => IpAddr::V6(*dst.ip()),
+        }
+    }
+    fn kind(&self) -> IpKind {
+        match self {
+            IpOptionalPair::V4 { .. } => IpKind::V4,
+            IpOptionalPair::V6 { .. } => IpKind::V6,
+        }
+    }
+}
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 enum IpKind {
     V4,
     V6,
 }
+
+impl IpKind {
+    /// Takes either a Windows error or IP_STATUS value
+    fn winerror(self, err: u32) -> Error {
+        match self {
+            IpKind::V4 => Error::from_winerror(err),
+            IpKind::V6 => Error::from_winerror6(err),
+        }
+    }
+}
+// The optional behaviours of an AsyncPinger, which its futures also need.
+#[derive(Clone, Default)]
+struct Policies {
+    retry: Option<Arc<RetryPolicy>>,
+    rtt: Option<Arc<Estimator>>,
+    rate: Option<Arc<Limiter>>,
+    dispatcher: Option<Dispatcher>,
+}
+
+impl Policies {
+    fn timeout(&self, dst: IpAddr, fixed: u32) -> u32 {
+        self.rtt.as_ref().map_or(fixed, |rtt| rtt.timeout(dst))
+    }
+    // Gets when a request to dst may be sent, or None for straight away.
+    fn not_before(&self, dst: IpAddr, earliest: Option<Instant>) -> Option<Instant> {
+        match &self.rate {
+            Some(rate) => Some(rate.reserve(dst, earliest.unwrap_or_else(Instant::now))),
+            None => earliest,
+        }
+    }
+}
 #[derive(Clone)]
 struct Worker {
     inner: SyncSender<Job>,
 }
-struct Job {
+// Everything needed to send (or resend) a request, except the buffer.
+#[derive(Copy, Clone)]
+struct Request {
     pair: IpOptionalPair,
+    ttl: u8,
+    timeout: u32,
+    df: bool,
+}
+struct Job {
+    request: Request,
     data_ptr: *mut VOID,
     data_len: u16,
     reply_ptr: *mut VOID,
     reply_len: u32,
-    ttl: u8,
-    timeout: u32,
-    df: bool,
-    cx: Arc<Mutex<State>>,
+    not_before: Option<Instant>,
+    cx: Arc<Shared>,
 }
 unsafe impl Send for Job {}
 
+impl Job {
+    // The buffer must be moved into cx before the job is sent. Moving the buffer
+    // does not move the heap allocations the pointers refer to.
+    fn new(
+        request: Request,
+        buf: &mut Buffer,
+        not_before: Option<Instant>,
+        cx: Arc<Shared>,
+    ) -> Self {
+        Self {
+            request,
+            data_ptr: buf.request_data_ptr(),
+            data_len: buf.request_data_len(),
+            reply_ptr: buf.reply_data_ptr(),
+            reply_len: buf.reply_data_len(),
+            not_before,
+            cx,
+        }
+    }
+}
+
+// A job waiting in the worker thread until it is due, ordered soonest first.
+struct Delayed(Job);
+
+impl Delayed {
+    fn due(&self) -> Instant {
+        self.0.not_before.unwrap()
+    }
+}
+impl PartialEq for Delayed {
+    fn eq(&self, other: &Self) -> bool {
+        self.due() == other.due()
+    }
+}
+impl Eq for Delayed {}
+impl PartialOrd for Delayed {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Delayed {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        other.due().cmp(&self.due())
+    }
+}
+
 impl Worker {
     fn new() -> Self {
         Self {
             inner: ASYNC_SENDER.clone(),
         }
     }
-    fn begin_v4(
-        &self,
-        src: Option<Ipv4Addr>,
-        dst: Ipv4Addr,
-        mut buf: Buffer,
-        ttl: u8,
-        timeout: u32,
-        df: bool,
-    ) -> PingFuture {
-        let data_ptr = buf.request_data_ptr();
-        let data_len = buf.request_data_len();
-        let reply_ptr = buf.reply_data_ptr();
-        let reply_len = buf.reply_data_len();
-        let state = Arc::new(Mutex::new(State::Unpolled(buf)));
-        let cx = state.clone();
-        let job = Job {
-            pair: IpOptionalPair::V4 { src, dst },
-            data_ptr,
-            data_len,
-            reply_ptr,
-            reply_len,
-            ttl,
-            timeout,
-            df,
-            cx,
+    // Creates the future and job for a request, which must be submitted for the future to complete.
+    fn prepare(&self, request: Request, mut buf: Buffer, policies: Policies) -> (PingFuture, Job) {
+        buf.set_attempts(1);
+        buf.init_for_send();
+        let shared = Arc::new(Shared::new());
+        let not_before = policies.not_before(request.pair.dst(), None);
+        let job = Job::new(request, &mut buf, not_before, shared.clone());
+        unsafe { shared.put_buffer(buf) };
+        let future = PingFuture {
+            shared,
+            kind: request.pair.kind(),
+            worker: self.clone(),
+            request,
+            policies,
         };
-        self.inner.send(job).unwrap();
-        unsafe { SetEvent(INPUT_EVENT) };
-        PingFuture {
-            state,
-            kind: IpKind::V4,
-        }
+        (future, job)
     }
-    fn begin_v6(
-        &self,
-        src: Option<Ipv6Addr>,
-        dst: Ipv6Addr,
-        mut buf: Buffer,
-        ttl: u8,
-        timeout: u32,
-        df: bool,
-    ) -> PingFuture {
-        let data_ptr = buf.request_data_ptr();
-        let data_len = buf.request_data_len();
-        let reply_ptr = buf.reply_data_ptr();
-        let reply_len = buf.reply_data_len();
-        let state = Arc::new(Mutex::new(State::Unpolled(buf)));
-        let cx = state.clone();
-        let job = Job {
-            pair: IpOptionalPair::V6 { src, dst },
-            data_ptr,
-            data_len,
-            reply_ptr,
-            reply_len,
-            ttl,
-            timeout,
-            df,
-            cx,
-        };
-        self.inner.send(job).unwrap();
-        unsafe { SetEvent(INPUT_EVENT) };
-        PingFuture {
-            state,
-            kind: IpKind::V6,
+    // Sends jobs to the worker thread, and wakes it once they've all been sent.
+    fn submit(&self, jobs: impl IntoIterator<Item = Job>) {
+        // The worker thread can't send to itself, as it would block if the channel is full
+        if LOCAL_JOBS.with(|local| local.borrow().is_some()) {
+            let jobs: Vec<_> = jobs.into_iter().collect();
+            LOCAL_JOBS.with(|local| local.borrow_mut().as_mut().unwrap().extend(jobs));
+            return;
+        }
+        for job in jobs {
+            if let Err(TrySendError::Full(job)) = self.inner.try_send(job) {
+                // Wake the worker thread to make room
+                unsafe { SetEvent(INPUT_EVENT) };
+                self.inner.send(job).unwrap();
+            }
         }
+        unsafe { SetEvent(INPUT_EVENT) };
     }
 }
 
+thread_local! {
+    // Jobs submitted by the worker thread itself, eg retries started from a callback.
+    // This is only Some on the worker thread.
+    static LOCAL_JOBS: RefCell<Option<Vec<Job>>> = const { RefCell::new(None) };
+}
+
 static mut INPUT_EVENT: HANDLE = NULL;
 static mut ICMP_HANDLE: HANDLE = INVALID_HANDLE_VALUE;
 static mut ICMP6_HANDLE: HANDLE = INVALID_HANDLE_VALUE;
@@ -350,68 +605,90 @@ lazy_static! {
             ICMP6_HANDLE = Icmp6CreateFile();
         }
 
-        thread::spawn(move||loop {
-            // WaitForSingleObjectEx returns if INPUT_EVENT is signaled, or if callback_fn is called
-            match unsafe { WaitForSingleObjectEx(INPUT_EVENT, INFINITE, TRUE) } {
-                WAIT_IO_COMPLETION | WAIT_OBJECT_0 => while try_recv_job(&rx) {},
-                WAIT_FAILED => {
-                    let err = Error::from_lasterror();
-                    panic!("AsyncPinger thread failed to wait in event loop: {}", err)
-                }
-                x => unreachable!("unexpected return from WaitForSingleObjectEx: {:x}", x)
-            }
-        });
+        thread::spawn(move || worker_loop(rx));
 
         tx
     };
 }
 
+fn worker_loop(rx: Receiver<Job>) {
+    let mut delayed = BinaryHeap::new();
+    LOCAL_JOBS.with(|local| *local.borrow_mut() = Some(Vec::new()));
+    loop {
+        // Wake up in time for the next delayed job, if there is one
+        let timeout = delayed.peek().map_or(INFINITE, |job: &Delayed| {
+            let wait = job.due().saturating_duration_since(Instant::now());
+            // Round up, so the job is due when the wait times out
+            ((wait.as_micros() + 999) / 1000).min(INFINITE as u128 - 1) as DWORD
+        });
+        // WaitForSingleObjectEx returns if INPUT_EVENT is signaled, if callback_fn is called,
+        // or if the next delayed job is due
+        match unsafe { WaitForSingleObjectEx(INPUT_EVENT, timeout, TRUE) } {
+            WAIT_IO_COMPLETION | WAIT_OBJECT_0 | WAIT_TIMEOUT => {
+                while try_recv_job(&rx, &mut delayed) {}
+                let local = LOCAL_JOBS.with(|local| local.borrow_mut().replace(Vec::new()));
+                for job in local.into_iter().flatten() {
+                    schedule_job(job, &mut delayed);
+                }
+                send_due_jobs(&mut delayed);
+            }
+            WAIT_FAILED => {
+                let err = Error::from_lasterror();
+                panic!("AsyncPinger thread failed to wait in event loop: {}", err)
+            }
+            x => unreachable!("unexpected return from WaitForSingleObjectEx: {:x}", x),
+        }
+    }
+}
+
 #[inline]
-fn try_recv_job(rx: &Receiver<Job>) -> bool {
-    let job = match rx.try_recv() {
-        Ok(job) => job,
+fn try_recv_job(rx: &Receiver<Job>, delayed: &mut BinaryHeap<Delayed>) -> bool {
+    match rx.try_recv() {
+        Ok(job) => schedule_job(job, delayed),
         _ => return false,
-    };
+    }
+    true
+}
+
+#[inline]
+fn schedule_job(job: Job, delayed: &mut BinaryHeap<Delayed>) {
+    match job.not_before {
+        Some(at) if at > Instant::now() => delayed.push(Delayed(job)),
+        _ => send_job(job),
+    }
+}
+
+#[inline]
+fn send_due_jobs(delayed: &mut BinaryHeap<Delayed>) {
+    let now = Instant::now();
+    while delayed.peek().map_or(false, |job| job.due() <= now) {
+        send_job(delayed.pop().unwrap().0);
+    }
+}
+
+fn send_job(job: Job) {
     let mut ip_opts = IP_OPTION_INFORMATION {
-        Ttl: job.ttl,
-        Flags: if job.df { IP_FLAG_DF } else { 0 },
+        Ttl: job.request.ttl,
+        Flags: if job.request.df { IP_FLAG_DF } else { 0 },
         ..Default::default()
     };
     let arcptr = Arc::into_raw(job.cx);
 
     #[inline]
-    fn after_send(ret: u32, arcptr: *const Mutex<State>) {
+    fn after_send(ret: u32, arcptr: *const Shared) {
         if ret != 0 {
             let arc = unsafe { Arc::from_raw(arcptr) };
-            let mut lock = arc.lock().unwrap();
-            let state = replace(&mut *lock, State::Invalid);
-            match state {
-                State::Unpolled(buf) => *lock = State::FailedAsyncSend(buf, ret),
-                State::Polled(buf, waker) => {
-                    *lock = State::FailedAsyncSend(buf, ret);
-                    waker.wake();
-                }
-                _ => {} // Leave state as Invalid, pushes panic out of async thread
-            }
+            arc.complete(State::FailedAsyncSend(ret));
         }
         let err = unsafe { GetLastError() };
         if err != ERROR_IO_PENDING {
             let arc = unsafe { Arc::from_raw(arcptr) };
-            let mut lock = arc.lock().unwrap();
-            let state = replace(&mut *lock, State::Invalid);
-            match state {
-                State::Unpolled(buf) => *lock = State::Failed(buf, err),
-                State::Polled(buf, waker) => {
-                    *lock = State::Failed(buf, err);
-                    waker.wake();
-                }
-                _ => {} // Leave state as Invalid, pushes panic out of async thread
-            }
+            arc.complete(State::Failed(err));
         }
     }
 
     use IpOptionalPair::{V4, V6};
-    match job.pair {
+    match job.request.pair {
         V4 {
             src: Some(src),
             dst,
@@ -429,7 +706,7 @@ fn try_recv_job(rx: &Receiver<Job>) -> bool {
                     &mut ip_opts,
                     job.reply_ptr,
                     job.reply_len,
-                    job.timeout,
+                    job.request.timeout,
                 )
             };
             after_send(ret, arcptr);
@@ -447,22 +724,16 @@ fn try_recv_job(rx: &Receiver<Job>) -> bool {
                     &mut ip_opts,
                     job.reply_ptr,
                     job.reply_len,
-                    job.timeout,
+                    job.request.timeout,
                 )
             };
             after_send(ret, arcptr);
         }
         V6 { src, dst } => {
-            let mut src = SOCKADDR_IN6 {
-                sin6_family: AF_INET6 as _,
-                sin6_addr: windows_ipv6(src.unwrap_or(Ipv6Addr::UNSPECIFIED)),
-                ..Default::default()
-            };
-            let mut dst = SOCKADDR_IN6 {
-                sin6_family: AF_INET6 as _,
-                sin6_addr: windows_ipv6(dst),
-                ..Default::default()
-            };
+            let mut src = windows_sockaddr6(
+                src.unwrap_or_else(|| SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0)),
+            );
+            let mut dst = windows_sockaddr6(dst);
             let ret = unsafe {
                 Icmp6SendEcho2(
                     ICMP6_HANDLE,
@@ -476,45 +747,217 @@ fn try_recv_job(rx: &Receiver<Job>) -> bool {
                     &mut ip_opts,
                     job.reply_ptr,
                     job.reply_len,
-                    job.timeout,
+                    job.request.timeout,
                 )
             };
             after_send(ret, arcptr);
         }
     }
-    true
 }
 
 extern "system" fn callback_fn(
-    state_arc: *const Mutex<State>,
+    state_arc: *const Shared,
     _io_status_block: *mut VOID,
     _rsvd: ULONG,
 ) {
     let state_arc = unsafe { Arc::from_raw(state_arc) };
-    let mut lock = state_arc.lock().unwrap();
-    let state = replace(&mut *lock, State::Invalid);
-    match state {
-        State::Unpolled(buf) => *lock = State::Ready(buf),
-        State::Polled(buf, waker) => {
-            *lock = State::Ready(buf);
-            waker.wake();
+    state_arc.complete(State::Ready);
+}
+
+impl Shared {
+    fn new() -> Self {
+        Self {
+            state: AtomicU64::new(State::Pending.encode()),
+            buf: UnsafeCell::new(None),
+            notify: Registration {
+                state: AtomicU8::new(WAITING),
+                notify: UnsafeCell::new(None),
+            },
+        }
+    }
+    fn state(&self) -> State {
+        State::decode(self.state.load(Ordering::Acquire))
+    }
+    // Moves a pending request to a completed state, and notifies anything waiting on it.
+    fn complete(&self, state: State) {
+        self.state.store(state.encode(), Ordering::Release);
+        if let Some(notify) = self.notify.take() {
+            notify.notify();
         }
-        _ => {} // Leave state as Invalid, pushes panic out of async thread
+    }
+    // Safety: only the future may use the buffer, and only before the request is sent
+    // or after it completes, as shown by the state. The worker thread never uses it.
+    unsafe fn put_buffer(&self, buf: Buffer) {
+        *self.buf.get() = Some(buf);
+    }
+    unsafe fn take_buffer(&self) -> Buffer {
+        (*self.buf.get()).take().unwrap()
     }
 }
 
-impl Future for PingFuture {
-    type Output = AsyncResult;
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut lock = self.state.lock().unwrap();
-        let state = replace(&mut *lock, State::Invalid);
-        match state {
-            State::Unpolled(buf) | State::Polled(buf, _) => {
-                *lock = State::Polled(buf, cx.waker().clone());
-                Poll::Pending
+impl State {
+    fn encode(self) -> u64 {
+        let (tag, err) = match self {
+            State::Pending => (0, 0),
+            State::Ready => (1, 0),
+            State::Failed(err) => (2, err),
+            State::FailedAsyncSend(err) => (3, err),
+            State::Taken => (4, 0),
+        };
+        (err as u64) << 32 | tag
+    }
+    fn decode(word: u64) -> Self {
+        let err = (word >> 32) as u32;
+        match word as u32 {
+            0 => State::Pending,
+            1 => State::Ready,
+            2 => State::Failed(err),
+            3 => State::FailedAsyncSend(err),
+            _ => State::Taken,
+        }
+    }
+}
+
+impl Registration {
+    // Registers notify, replacing any earlier one. Only the future registers, so this
+    // never runs on two threads at once. If a take is running, it may have missed
+    // notify, so notify is returned to be notified straight away.
+    fn register(&self, notify: Notify) -> Option<Notify> {
+        let registering =
+            self.state
+                .compare_exchange(WAITING, REGISTERING, Ordering::Acquire, Ordering::Acquire);
+        if registering.is_err() {
+            return Some(notify);
+        }
+        let old = unsafe { (*self.notify.get()).replace(notify) };
+        let registered =
+            self.state
+                .compare_exchange(REGISTERING, WAITING, Ordering::AcqRel, Ordering::Acquire);
+        drop(old);
+        match registered {
+            Ok(_) => None,
+            Err(_) => {
+                // A take started while registering, and left the notify for us
+                let notify = unsafe { (*self.notify.get()).take() };
+                self.state.swap(WAITING, Ordering::AcqRel);
+                notify
+            }
+        }
+    }
+    // Takes the registered notify, if there is one and it isn't being registered.
+    fn take(&self) -> Option<Notify> {
+        match self.state.fetch_or(TAKING, Ordering::AcqRel) {
+            WAITING => {
+                let notify = unsafe { (*self.notify.get()).take() };
+                self.state.fetch_and(!TAKING, Ordering::Release);
+                notify
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Notify {
+    fn notify(self) {
+        match self {
+            Notify::Thread(thread) => thread.unpark(),
+            Notify::Waker(waker) => waker.wake(),
+            Notify::Callback(future, callback) => future.on_complete_boxed(callback),
+        }
+    }
+}
+
+impl PingFuture {
+    /// Checks whether the request has completed, so polling or waiting won't block.
+    /// If the request failed and will be retried, polling or waiting starts the retry
+    /// instead of completing.
+    pub fn is_ready(&self) -> bool {
+        self.shared.state() != State::Pending
+    }
+    /// Blocks the current thread until the request completes. This is for code which
+    /// isn't async, and doesn't need an executor.
+    pub fn wait(mut self) -> AsyncResult {
+        loop {
+            if let Poll::Ready(res) = self.poll_with(Notify::Thread(thread::current())) {
+                return res;
+            }
+            thread::park();
+        }
+    }
+    /// Blocks the current thread until the request completes, or the timeout passes.
+    /// On timeout, the future is returned so it can be waited on or awaited again.
+    #[allow(clippy::result_large_err)]
+    pub fn wait_timeout(mut self, timeout: Duration) -> Result<AsyncResult, Self> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Poll::Ready(res) = self.poll_with(Notify::Thread(thread::current())) {
+                return Ok(res);
             }
-            State::Ready(mut buf) => {
-                drop(lock);
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(self);
+            }
+            thread::park_timeout(deadline - now);
+        }
+    }
+    /// Blocks the current thread until every request completes, and returns the
+    /// results in the same order. The requests are all in flight at once, so this
+    /// takes about as long as the slowest one.
+    pub fn wait_all(futures: impl IntoIterator<Item = Self>) -> Vec<AsyncResult> {
+        let futures: Vec<_> = futures.into_iter().collect();
+        futures.into_iter().map(PingFuture::wait).collect()
+    }
+    /// Calls the callback with the result once the request completes (including any retries),
+    /// instead of polling or waiting. The callback is run by the pinger's
+    /// [dispatcher](AsyncPinger::set_callback_dispatcher) if it has one. Otherwise, it runs
+    /// on the worker thread shared by all AsyncPingers, or on this thread if the request has
+    /// already completed, so it should be quick, eg sending the result to a channel.
+    pub fn on_complete<F>(self, callback: F)
+    where
+        F: FnOnce(AsyncResult) + Send + 'static,
+    {
+        self.on_complete_boxed(Box::new(callback))
+    }
+    fn on_complete_boxed(mut self, mut callback: Callback) {
+        loop {
+            if let Poll::Ready(res) = self.try_finish() {
+                return match &self.policies.dispatcher {
+                    Some(dispatcher) => dispatcher(Box::new(move || callback(res))),
+                    None => callback(res),
+                };
+            }
+            let shared = self.shared.clone();
+            let mut notify = shared.notify.register(Notify::Callback(self, callback));
+            if notify.is_none() && shared.state() != State::Pending {
+                // The request completed before the callback was registered
+                notify = shared.notify.take();
+            }
+            match notify {
+                Some(Notify::Callback(future, cb)) => {
+                    self = future;
+                    callback = cb;
+                }
+                // The worker thread will run the callback
+                _ => return,
+            }
+        }
+    }
+    fn poll_with(&mut self, notify: Notify) -> Poll<AsyncResult> {
+        if let Poll::Ready(res) = self.try_finish() {
+            return Poll::Ready(res);
+        }
+        if let Some(notify) = self.shared.notify.register(notify) {
+            notify.notify();
+        }
+        // Check again, in case the request completed before notify was registered
+        self.try_finish()
+    }
+    // Returns the result if the request has completed, or retries it.
+    fn try_finish(&mut self) -> Poll<AsyncResult> {
+        let (result, buf) = match self.shared.state() {
+            State::Pending => return Poll::Pending,
+            State::Ready => {
+                let mut buf = unsafe { self.shared.take_buffer() };
                 let ret = unsafe {
                     match self.kind {
                         IpKind::V4 => IcmpParseReplies(buf.reply_data_ptr(), buf.reply_data_len()),
@@ -522,7 +965,7 @@ impl Future for PingFuture {
                     }
                 };
                 let result = if ret == 0 {
-                    Err(Error::from_lasterror())
+                    Err(self.kind.winerror(unsafe { GetLastError() }))
                 } else {
                     let (status, rtt) = match self.kind {
                         IpKind::V4 => {
@@ -532,7 +975,10 @@ impl Future for PingFuture {
                             let reply = buf.as_echo_reply32().unwrap();
 
                             let (status, rtt) = (reply.Status, reply.RoundTripTime);
-                            buf.set_filled4();
+                            #[cfg(target_pointer_width = "32")]
+                            buf.set_filled4(ret);
+                            #[cfg(target_pointer_width = "64")]
+                            buf.set_filled4x32(ret);
                             (status, rtt)
                         }
                         IpKind::V6 => {
@@ -545,26 +991,260 @@ impl Future for PingFuture {
                     if status == IP_SUCCESS {
                         Ok(rtt)
                     } else {
-                        Err(Error::from_iperror(status))
+                        Err(self.kind.winerror(status))
                     }
                 };
-                Poll::Ready(AsyncResult {
-                    result,
-                    buffer: buf,
-                })
+                (result, buf)
             }
-            State::Failed(buf, err) => {
-                drop(lock);
-                Poll::Ready(AsyncResult {
-                    result: Err(Error::from_winerror(err)),
-                    buffer: buf,
-                })
+            State::Failed(err) => {
+                let buf = unsafe { self.shared.take_buffer() };
+                (Err(self.kind.winerror(err)), buf)
             }
-            State::FailedAsyncSend(_buf, err) => unreachable!(
+            State::FailedAsyncSend(err) => unreachable!(
                 "Failed to send async. Expected return of 0, got {} instead.",
                 err
             ),
-            State::Invalid => unreachable!(),
+            State::Taken => unreachable!(),
+        };
+        let taken = State::Taken.encode();
+        self.shared.state.store(taken, Ordering::Relaxed);
+        let dst = self.request.pair.dst();
+        if let Some(rtt) = &self.policies.rtt {
+            rtt.observe(dst, result);
+        }
+        match (result, self.policies.retry.clone()) {
+            (Err(err), Some(policy)) if policy.should_retry(err, buf.attempts()) => {
+                let mut buf = buf;
+                let attempts = buf.attempts();
+                buf.set_attempts(attempts + 1);
+                buf.init_for_send();
+                let timeout = self.policies.timeout(dst, self.request.timeout);
+                self.request.timeout = timeout;
+                let not_before = Instant::now() + policy.delay(attempts);
+                let not_before = self.policies.not_before(dst, Some(not_before));
+                let job = Job::new(self.request, &mut buf, not_before, self.shared.clone());
+                unsafe { self.shared.put_buffer(buf) };
+                let pending = State::Pending.encode();
+                self.shared.state.store(pending, Ordering::Relaxed);
+                self.worker.submit(Some(job));
+                Poll::Pending
+            }
+            _ => Poll::Ready(AsyncResult {
+                result,
+                buffer: buf,
+            }),
+        }
+    }
+}
+
+impl Future for PingFuture {
+    type Output = AsyncResult;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.get_mut().poll_with(Notify::Waker(cx.waker().clone()))
+    }
+}
+
+impl BatchShared {
+    fn push(&self, index: usize, res: AsyncResult) {
+        let mut completed = self.completed.lock().unwrap();
+        completed.results.push_back((index, res));
+        if let Some(waker) = completed.waker.take() {
+            waker.wake();
+        }
+        self.done.notify_all();
+    }
+}
+
+impl PingBatch {
+    /// Gets the number of requests which haven't been returned yet.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+    /// Waits for the next request to complete, and returns its index and result,
+    /// or None if every result has been returned.
+    pub async fn next(&mut self) -> Option<(usize, AsyncResult)> {
+        poll_fn(|cx| self.poll_next(cx)).await
+    }
+    /// Polls for the next request to complete. This is the same as
+    /// `Stream::poll_next` from the futures crate.
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<(usize, AsyncResult)>> {
+        if self.remaining == 0 {
+            return Poll::Ready(None);
+        }
+        let mut completed = self.shared.completed.lock().unwrap();
+        match completed.results.pop_front() {
+            Some(next) => {
+                self.remaining -= 1;
+                Poll::Ready(Some(next))
+            }
+            None => {
+                completed.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+    /// Blocks the current thread until the next request completes, and returns its
+    /// index and result, or None if every result has been returned.
+    pub fn wait_next(&mut self) -> Option<(usize, AsyncResult)> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let completed = self.shared.completed.lock().unwrap();
+        let mut completed = self
+            .shared
+            .done
+            .wait_while(completed, |completed| completed.results.is_empty())
+            .unwrap();
+        self.remaining -= 1;
+        completed.results.pop_front()
+    }
+    /// Blocks the current thread until every request completes, and returns the
+    /// results in the order the requests were given.
+    pub fn wait_all(mut self) -> Vec<AsyncResult> {
+        let mut results = Vec::with_capacity(self.remaining);
+        while let Some(next) = self.wait_next() {
+            results.push(next);
+        }
+        results.sort_by_key(|&(index, _)| index);
+        results.into_iter().map(|(_, res)| res).collect()
+    }
+}
+
+// Compares the atomic completion state with the Mutex based one it replaced, using a
+// simulated backend thread which completes requests instead of the ICMP API. Run with
+// cargo test --release completion_throughput -- --ignored --nocapture
+#[cfg(test)]
+mod bench {
+    use super::*;
+    use std::{mem::replace, task::Wake};
+    use winapi::um::ipexport::IP_REQ_TIMED_OUT;
+
+    const REQUESTS: usize = 200_000;
+    const IN_FLIGHT: usize = 1000;
+
+    struct CountWaker(AtomicUsize);
+    impl Wake for CountWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Runs batches of requests through a backend thread, which calls complete on the
+    // backend's half of each request, polling each one before it's sent and until it completes.
+    fn run<T, B, P>(new: impl Fn() -> (T, B), complete: fn(B), mut poll: P) -> Duration
+    where
+        B: Send + 'static,
+        P: FnMut(&mut T, &Waker) -> bool,
+    {
+        let (tx, rx) = mpsc::channel::<B>();
+        let backend = thread::spawn(move || rx.iter().for_each(complete));
+        let waker = Waker::from(Arc::new(CountWaker(AtomicUsize::new(0))));
+        let start = Instant::now();
+        for _ in 0..REQUESTS / IN_FLIGHT {
+            let (mut reqs, backend_reqs): (Vec<T>, Vec<B>) = (0..IN_FLIGHT).map(|_| new()).unzip();
+            for (req, backend_req) in reqs.iter_mut().zip(backend_reqs) {
+                assert!(!poll(req, &waker));
+                tx.send(backend_req).unwrap();
+            }
+            for req in &mut reqs {
+                while !poll(req, &waker) {
+                    thread::yield_now();
+                }
+            }
         }
+        let elapsed = start.elapsed();
+        drop(tx);
+        backend.join().unwrap();
+        elapsed
+    }
+
+    // The previous implementation, reduced to the parts used here
+    enum Locked {
+        Unpolled(Buffer),
+        Polled(Buffer, Waker),
+        Failed(Buffer, u32),
+        Invalid,
+    }
+
+    fn mutex_state() -> Duration {
+        let new = || {
+            let state = Arc::new(Mutex::new(Locked::Unpolled(Buffer::new())));
+            (state.clone(), state)
+        };
+        let complete = |state: Arc<Mutex<Locked>>| {
+            let mut lock = state.lock().unwrap();
+            match replace(&mut *lock, Locked::Invalid) {
+                Locked::Unpolled(buf) => *lock = Locked::Failed(buf, IP_REQ_TIMED_OUT),
+                Locked::Polled(buf, waker) => {
+                    *lock = Locked::Failed(buf, IP_REQ_TIMED_OUT);
+                    waker.wake();
+                }
+                _ => {}
+            }
+        };
+        run(new, complete, |state: &mut Arc<Mutex<Locked>>, waker| {
+            let mut lock = state.lock().unwrap();
+            match replace(&mut *lock, Locked::Invalid) {
+                Locked::Unpolled(buf) | Locked::Polled(buf, _) => {
+                    *lock = Locked::Polled(buf, waker.clone());
+                    false
+                }
+                Locked::Failed(buf, err) => {
+                    drop(lock);
+                    assert_eq!(Error::Timeout, IpKind::V4.winerror(err));
+                    drop(buf);
+                    true
+                }
+                Locked::Invalid => true,
+            }
+        })
+    }
+
+    fn atomic_state() -> Duration {
+        let worker = Worker::new();
+        let request = Request {
+            pair: IpOptionalPair::V4 {
+                src: None,
+                dst: Ipv4Addr::LOCALHOST,
+            },
+            ttl: 255,
+            timeout: 2000,
+            df: false,
+        };
+        let new = || {
+            let shared = Arc::new(Shared::new());
+            unsafe { shared.put_buffer(Buffer::new()) };
+            let future = PingFuture {
+                shared: shared.clone(),
+                kind: IpKind::V4,
+                worker: worker.clone(),
+                request,
+                policies: Policies::default(),
+            };
+            (future, shared)
+        };
+        let complete = |shared: Arc<Shared>| shared.complete(State::Failed(IP_REQ_TIMED_OUT));
+        run(new, complete, |future: &mut PingFuture, waker| match future
+            .poll_with(Notify::Waker(waker.clone()))
+        {
+            Poll::Ready(res) => {
+                assert_eq!(Err(Error::Timeout), res.result);
+                true
+            }
+            Poll::Pending => false,
+        })
+    }
+
+    #[test]
+    #[ignore]
+    fn completion_throughput() {
+        let (mutex, atomic) = (mutex_state(), atomic_state());
+        let rate = |elapsed: Duration| REQUESTS as f64 / elapsed.as_secs_f64();
+        println!("Mutex state:  {:?} ({:.0} requests/s)", mutex, rate(mutex));
+        println!(
+            "Atomic state: {:?} ({:.0} requests/s)",
+            atomic,
+            rate(atomic)
+        );
     }
 }
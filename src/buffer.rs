@@ -3,7 +3,7 @@ use static_assertions::{assert_impl_all, const_assert, const_assert_eq};
 use winapi::um::ipexport::ICMP_ECHO_REPLY32;
 use winapi::{
     shared::ntdef::VOID,
-    um::ipexport::{ICMPV6_ECHO_REPLY, ICMP_ECHO_REPLY},
+    um::ipexport::{ICMPV6_ECHO_REPLY, ICMP_ECHO_REPLY, IP_SUCCESS},
 };
 
 use std::{
@@ -11,7 +11,10 @@ use std::{
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
 };
 
-use crate::util::{rust_ipv4, rust_ipv6};
+use crate::{
+    util::{rust_ipv4, rust_ipv6},
+    Error,
+};
 
 // Chunk is a lump of u8, apropriately sized and aligned
 // for the necessary ICMP(V6)_ECHO_REPLY(32) types on
@@ -36,14 +39,38 @@ const_assert!(CHUNK_SIZE >= align_of::<ICMP_ECHO_REPLY32>());
 pub struct Buffer {
     pub request_data: Vec<u8>,
     reply_data: Vec<Chunk>,
+    max_replies: usize,
+    attempts: u32,
     state: ReplyState,
 }
 assert_impl_all!(Buffer: Send, Sync);
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 enum ReplyState {
     Empty,
-    Filled4 { data_len: usize },
-    Filled6 { data_len: usize },
+    Filled4 {
+        data_len: usize,
+        count: usize,
+    },
+    // Async replies on 64 bit platforms are ICMP_ECHO_REPLY32
+    #[cfg(all(target_pointer_width = "64", feature = "async"))]
+    Filled4x32 {
+        data_len: usize,
+        count: usize,
+    },
+    Filled6 {
+        data_len: usize,
+    },
+}
+/// A host which replied to a request, as returned by [`Buffer::responders`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Responder {
+    /// The address of the responding host.
+    pub addr: IpAddr,
+    /// The round trip time in milliseconds.
+    pub rtt: u32,
+    /// The IP TTL of the reply. This is only available for IPv4.
+    pub ttl: Option<u8>,
 }
 
 impl Default for Buffer {
@@ -58,6 +85,8 @@ impl Buffer {
         Self {
             request_data: Vec::new(),
             reply_data: Vec::new(),
+            max_replies: 1,
+            attempts: 0,
             state: ReplyState::Empty,
         }
     }
@@ -66,35 +95,41 @@ impl Buffer {
         Self {
             request_data,
             reply_data: Vec::new(),
+            max_replies: 1,
+            attempts: 0,
             state: ReplyState::Empty,
         }
     }
+    /// Sets the maximum number of replies this buffer has room for. This defaults to 1, and
+    /// only needs changed when sending to a broadcast or multicast address, where more than
+    /// one host may reply. Values less than 1 are treated as 1.
+    ///
+    /// Note that the OS decides how many replies are actually returned. In particular,
+    /// ICMPv6 requests only ever return one reply.
+    pub fn set_max_replies(&mut self, max_replies: usize) {
+        self.max_replies = max_replies.max(1);
+    }
+    /// Gets the maximum number of replies this buffer has room for.
+    pub fn max_replies(&self) -> usize {
+        self.max_replies
+    }
+    /// Gets the number of attempts taken by the last request this buffer was involved in.
+    /// This is 1 unless the request was retried by a [`RetryPolicy`](crate::RetryPolicy),
+    /// or 0 if the buffer wasn't used in a request.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+    pub(crate) fn set_attempts(&mut self, attempts: u32) {
+        self.attempts = attempts;
+    }
 
+    // Discards any replies, keeping the reply buffer's allocation.
+    pub(crate) fn recycle(&mut self) {
+        self.attempts = 0;
+        self.state = ReplyState::Empty;
+    }
     pub(crate) fn init_for_send(&mut self) {
-        // Reply buffer must be large enough for:
-        // 1. Any possible ICMP_ECHO(V6)_REPLY(32) type +
-        // 2. An ICMP error (8 bytes) +
-        // 3. An IO_STATUS_BLOCK (up to 16 bytes) +
-        // 4. The length of the request data
-        #[cfg(target_pointer_width = "64")]
-        const MIN_ECHO_REPLY_SIZE: usize = {
-            const_assert!(size_of::<ICMPV6_ECHO_REPLY>() <= size_of::<ICMP_ECHO_REPLY>());
-            const_assert!(size_of::<ICMP_ECHO_REPLY32>() <= size_of::<ICMP_ECHO_REPLY>());
-            size_of::<ICMP_ECHO_REPLY>()
-        };
-        #[cfg(target_pointer_width = "32")]
-        const MIN_ECHO_REPLY_SIZE: usize = {
-            const_assert!(size_of::<ICMP_ECHO_REPLY>() <= size_of::<ICMPV6_ECHO_REPLY>());
-            size_of::<ICMPV6_ECHO_REPLY>()
-        };
-        const BASE_SIZE: usize = MIN_ECHO_REPLY_SIZE + 24;
-        let size = BASE_SIZE + self.request_data.len();
-
-        let chunks = size / CHUNK_SIZE
-            + match size % CHUNK_SIZE {
-                0 => 0,
-                _ => 1,
-            };
+        let chunks = chunks(reply_size(self.max_replies, self.request_data.len()));
         self.reply_data.resize(chunks, Chunk([0; CHUNK_SIZE]));
         self.state = ReplyState::Empty;
     }
@@ -146,9 +181,16 @@ impl Buffer {
             None
         }
     }
-    pub(crate) fn set_filled4(&mut self) {
+    pub(crate) fn set_filled4(&mut self, count: u32) {
         let data_len = self.as_echo_reply().unwrap().DataSize as usize;
-        self.state = ReplyState::Filled4 { data_len }
+        let count = (count as usize).min(self.max_replies);
+        self.state = ReplyState::Filled4 { data_len, count }
+    }
+    #[cfg(all(target_pointer_width = "64", feature = "async"))]
+    pub(crate) fn set_filled4x32(&mut self, count: u32) {
+        let data_len = self.as_echo_reply32().unwrap().DataSize as usize;
+        let count = (count as usize).min(self.max_replies);
+        self.state = ReplyState::Filled4x32 { data_len, count }
     }
     pub(crate) fn set_filled6(&mut self) {
         // RFC 4443, section 4.2, reply data MUST be same as request data
@@ -158,34 +200,15 @@ impl Buffer {
     /// Gets the reply data from the last ping this buffer was used in. The reply data may be empty
     /// if a reuqest was not send with this buffer, or if there was no reply to the sent request.
     pub fn reply_data(&self) -> &[u8] {
-        let (len, offset) = match self.state {
-            ReplyState::Empty => (0, 0),
-            ReplyState::Filled4 { data_len } => {
-                // No need to treat ICMP_ECHO_REPLY32 separately.
-                // IcmpParseReplies does not move the reply data when
-                // converting ICMP_ECHO_REPLY to ICMP_ECHO_REPLY32,
-                // so offset is still size of ICMP_ECHO_REPLY.
-                (data_len, size_of::<ICMP_ECHO_REPLY>())
-            }
-            ReplyState::Filled6 { data_len } => (data_len, size_of::<ICMPV6_ECHO_REPLY>()),
-        };
-
-        if len == 0 || offset + len > self.reply_data_len() as usize {
-            &[]
-        } else {
-            unsafe {
-                std::slice::from_raw_parts(
-                    self.reply_data_ptr_const().cast::<u8>().add(offset),
-                    len,
-                )
-            }
-        }
+        self.replies().next().map_or(&[], |reply| reply.data())
     }
     /// Gets the responding Ipv6Addr from the last request this buffer was involved in. Returns None
     /// if the last request was v6, the buffer wasn't used in a request, or there was no reply.
     pub fn responding_ipv4(&self) -> Option<Ipv4Addr> {
         let addr = match self.state {
             ReplyState::Filled4 { .. } => self.as_echo_reply().unwrap().Address,
+            #[cfg(all(target_pointer_width = "64", feature = "async"))]
+            ReplyState::Filled4x32 { .. } => self.as_echo_reply32().unwrap().Address,
             _ => return None,
         };
         Some(rust_ipv4(addr))
@@ -206,4 +229,377 @@ impl Buffer {
             .map(IpAddr::V4)
             .or_else(|| self.responding_ipv6().map(IpAddr::V6))
     }
+    /// Gets an iterator over every reply to the last request this buffer was involved in,
+    /// including replies reporting an error. There is usually at most one reply, unless
+    /// [`Buffer::set_max_replies`] was used.
+    pub fn replies(&self) -> Replies<'_> {
+        Replies {
+            area: &self.reply_data,
+            state: self.state,
+            index: 0,
+        }
+    }
+    /// Gets every host which successfully replied to the last request this buffer was involved
+    /// in. This is mostly useful for requests to broadcast or multicast addresses, see
+    /// [`Buffer::set_max_replies`]. Replies reporting an error are skipped.
+    pub fn responders(&self) -> Vec<Responder> {
+        self.replies()
+            .filter_map(|reply| {
+                reply.result().ok().map(|rtt| Responder {
+                    addr: reply.responder(),
+                    rtt,
+                    ttl: reply.ttl(),
+                })
+            })
+            .collect()
+    }
+}
+
+// Reply buffer must be large enough for:
+// 1. Any possible ICMP_ECHO(V6)_REPLY(32) type +
+// 2. An ICMP error (8 bytes) +
+// 3. An IO_STATUS_BLOCK (up to 16 bytes) +
+// 4. The length of the request data
+#[cfg(target_pointer_width = "64")]
+const MIN_ECHO_REPLY_SIZE: usize = size_of::<ICMP_ECHO_REPLY>();
+#[cfg(target_pointer_width = "64")]
+const_assert!(size_of::<ICMPV6_ECHO_REPLY>() <= MIN_ECHO_REPLY_SIZE);
+#[cfg(target_pointer_width = "64")]
+const_assert!(size_of::<ICMP_ECHO_REPLY32>() <= MIN_ECHO_REPLY_SIZE);
+#[cfg(target_pointer_width = "32")]
+const MIN_ECHO_REPLY_SIZE: usize = size_of::<ICMPV6_ECHO_REPLY>();
+#[cfg(target_pointer_width = "32")]
+const_assert!(size_of::<ICMP_ECHO_REPLY>() <= MIN_ECHO_REPLY_SIZE);
+// Every reply may be followed by its own ICMP error and data.
+const PER_REPLY_SIZE: usize = MIN_ECHO_REPLY_SIZE + 8;
+
+// Gets the size in bytes of a reply area with room for max_replies replies to a
+// request with data_len bytes of data.
+const fn reply_size(max_replies: usize, data_len: usize) -> usize {
+    max_replies * (PER_REPLY_SIZE + data_len) + 16
+}
+
+// Gets the number of chunks needed to hold size bytes.
+const fn chunks(size: usize) -> usize {
+    (size + CHUNK_SIZE - 1) / CHUNK_SIZE
+}
+
+/// Gets the size in bytes of a reply area for [`Pinger::send_borrowed`](crate::Pinger::send_borrowed),
+/// with room for a reply to a request with data_len bytes of data. This includes room to align
+/// the reply area, so any slice of this size will do.
+pub const fn reply_area_size(data_len: usize) -> usize {
+    // Only whole chunks of the aligned part are used, and up to CHUNK_SIZE - 1 bytes
+    // may come before it
+    chunks(reply_size(1, data_len)) * CHUNK_SIZE + CHUNK_SIZE - 1
+}
+
+/// A reply area borrowed from the caller, aligned for the reply structures.
+pub(crate) struct ReplyArea<'a> {
+    area: &'a mut [Chunk],
+    state: ReplyState,
+}
+
+impl<'a> ReplyArea<'a> {
+    pub(crate) fn new(bytes: &'a mut [u8]) -> Self {
+        // Safety: Chunk is a plain array of u8, so any bytes are a valid Chunk,
+        // and align_to_mut only returns the aligned middle of the slice
+        let (_, area, _) = unsafe { bytes.align_to_mut::<Chunk>() };
+        Self {
+            area,
+            state: ReplyState::Empty,
+        }
+    }
+    /// Checks whether there's room for a reply to a request with data_len bytes of data.
+    pub(crate) fn fits(&self, data_len: usize) -> bool {
+        self.len() as usize >= reply_size(1, data_len)
+    }
+    pub(crate) fn ptr(&mut self) -> *mut VOID {
+        self.area.as_mut_ptr() as *mut VOID
+    }
+    pub(crate) fn len(&self) -> u32 {
+        (self.area.len() * CHUNK_SIZE).min(u32::MAX as usize) as u32
+    }
+    /// Records that IcmpSendEcho wrote count replies, and returns the status and
+    /// round trip time of the first.
+    pub(crate) fn set_filled4(&mut self, count: u32) -> (u32, u32) {
+        // Safety: fits ensures there's room for a reply
+        let reply = unsafe { view::<ICMP_ECHO_REPLY>(self.area, 0).unwrap() };
+        let data_len = reply.DataSize as usize;
+        self.state = ReplyState::Filled4 {
+            data_len,
+            count: count as usize,
+        };
+        (reply.Status, reply.RoundTripTime)
+    }
+    /// Records that Icmp6SendEcho2 wrote a reply, and returns its status and round trip time.
+    pub(crate) fn set_filled6(&mut self, data_len: usize) -> (u32, u32) {
+        let reply = unsafe { view::<ICMPV6_ECHO_REPLY>(self.area, 0).unwrap() };
+        self.state = ReplyState::Filled6 { data_len };
+        (reply.Status, reply.RoundTripTime as u32)
+    }
+    pub(crate) fn into_view(self, result: Result<u32, Error>, attempts: u32) -> ReplyView<'a> {
+        ReplyView {
+            result,
+            attempts,
+            area: self.area,
+            state: self.state,
+        }
+    }
+}
+
+/// The replies to a request sent with [`Pinger::send_borrowed`](crate::Pinger::send_borrowed),
+/// which borrow the caller's reply area.
+#[derive(Clone, Debug)]
+pub struct ReplyView<'a> {
+    result: Result<u32, Error>,
+    attempts: u32,
+    area: &'a [Chunk],
+    state: ReplyState,
+}
+
+impl<'a> ReplyView<'a> {
+    /// Gets the result of the request. On success, returns the round trip time in milliseconds.
+    pub fn result(&self) -> Result<u32, Error> {
+        self.result
+    }
+    /// Gets the number of attempts taken, which is 0 if the request wasn't sent.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+    /// Gets the data of the first reply, or an empty slice if there was no reply.
+    pub fn reply_data(&self) -> &'a [u8] {
+        self.replies().next().map_or(&[], |reply| reply.data())
+    }
+    /// Gets the address of the host which sent the first reply.
+    pub fn responding_ip(&self) -> Option<IpAddr> {
+        self.replies().next().map(|reply| reply.responder())
+    }
+    /// Gets an iterator over every reply, including replies reporting an error.
+    pub fn replies(&self) -> Replies<'a> {
+        Replies {
+            area: self.area,
+            state: self.state,
+            index: 0,
+        }
+    }
+}
+
+/// A view of one reply stored in a [`Buffer`], as returned by [`Buffer::replies`].
+#[derive(Copy, Clone, Debug)]
+pub struct Reply<'a> {
+    addr: IpAddr,
+    status: u32,
+    rtt: u32,
+    ttl: Option<u8>,
+    data: &'a [u8],
+}
+
+impl<'a> Reply<'a> {
+    /// Gets the result of this reply. On success, returns the round trip time in milliseconds.
+    pub fn result(&self) -> Result<u32, Error> {
+        match (self.status, self.addr) {
+            (IP_SUCCESS, _) => Ok(self.rtt),
+            (status, IpAddr::V4(_)) => Err(Error::from_iperror(status)),
+            (status, IpAddr::V6(_)) => Err(Error::from_iperror6(status)),
+        }
+    }
+    /// Gets the address of the host which sent this reply. For an error, this is the
+    /// host which reported the error.
+    pub fn responder(&self) -> IpAddr {
+        self.addr
+    }
+    /// Gets the round trip time in milliseconds.
+    pub fn rtt(&self) -> u32 {
+        self.rtt
+    }
+    /// Gets the IP TTL of this reply. This is only available for IPv4.
+    pub fn ttl(&self) -> Option<u8> {
+        self.ttl
+    }
+    /// Gets the data of this reply.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+/// Serializes as a struct with the reply's result, responder, rtt, ttl and data.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Reply<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut out = serializer.serialize_struct("Reply", 5)?;
+        out.serialize_field("result", &self.result())?;
+        out.serialize_field("responder", &self.addr)?;
+        out.serialize_field("rtt", &self.rtt)?;
+        out.serialize_field("ttl", &self.ttl)?;
+        out.serialize_field("data", self.data)?;
+        out.end()
+    }
+}
+
+/// Serializes the request data, attempts and every [`Reply`]. Buffers can't be
+/// deserialized, as the replies only exist in the form Windows writes them.
+/// Use [`Responder`] or the replies' results for data which needs to be read back.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Buffer {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut out = serializer.serialize_struct("Buffer", 4)?;
+        out.serialize_field("request_data", &self.request_data)?;
+        out.serialize_field("max_replies", &self.max_replies)?;
+        out.serialize_field("attempts", &self.attempts)?;
+        out.serialize_field("replies", &self.replies().collect::<Vec<_>>())?;
+        out.end()
+    }
+}
+
+/// An iterator over the replies stored in a [`Buffer`], as returned by [`Buffer::replies`].
+#[derive(Clone, Debug)]
+pub struct Replies<'a> {
+    area: &'a [Chunk],
+    state: ReplyState,
+    index: usize,
+}
+
+impl<'a> Iterator for Replies<'a> {
+    type Item = Reply<'a>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let ret = reply_at(self.area, self.state, self.index)?;
+        self.index += 1;
+        Some(ret)
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let count = match self.state {
+            ReplyState::Empty => 0,
+            ReplyState::Filled4 { count, .. } => count,
+            #[cfg(all(target_pointer_width = "64", feature = "async"))]
+            ReplyState::Filled4x32 { count, .. } => count,
+            ReplyState::Filled6 { .. } => 1,
+        };
+        (0, Some(count.saturating_sub(self.index)))
+    }
+}
+
+fn reply_at(area: &[Chunk], state: ReplyState, index: usize) -> Option<Reply<'_>> {
+    // A single reply's data directly follows its reply structure. With several replies,
+    // Windows writes an array of reply structures followed by their data, so each reply's
+    // data is found by its data pointer, which is checked against the bounds of the reply area.
+    match state {
+        ReplyState::Filled4 { data_len, count } if index < count => {
+            let reply = unsafe { view::<ICMP_ECHO_REPLY>(area, index)? };
+            let data = if count == 1 {
+                data_at(area, size_of::<ICMP_ECHO_REPLY>(), data_len)
+            } else {
+                let offset = (reply.Data as usize).wrapping_sub(area.as_ptr() as usize);
+                data_at(area, offset, reply.DataSize as usize)
+            };
+            Some(Reply {
+                addr: IpAddr::V4(rust_ipv4(reply.Address)),
+                status: reply.Status,
+                rtt: reply.RoundTripTime,
+                ttl: Some(reply.Options.Ttl),
+                data,
+            })
+        }
+        #[cfg(all(target_pointer_width = "64", feature = "async"))]
+        ReplyState::Filled4x32 { data_len, count } if index < count => {
+            let reply = unsafe { view::<ICMP_ECHO_REPLY32>(area, index)? };
+            let data = if count == 1 {
+                // IcmpParseReplies does not move the reply data when
+                // converting ICMP_ECHO_REPLY to ICMP_ECHO_REPLY32,
+                // so offset is still size of ICMP_ECHO_REPLY.
+                data_at(area, size_of::<ICMP_ECHO_REPLY>(), data_len)
+            } else {
+                // Only the low 32 bits of the pointer are kept, which is
+                // still enough to find the offset into the reply area.
+                let offset = reply.Data.wrapping_sub(area.as_ptr() as usize as u32);
+                data_at(area, offset as usize, reply.DataSize as usize)
+            };
+            Some(Reply {
+                addr: IpAddr::V4(rust_ipv4(reply.Address)),
+                status: reply.Status,
+                rtt: reply.RoundTripTime,
+                ttl: Some(reply.Options.Ttl),
+                data,
+            })
+        }
+        ReplyState::Filled6 { data_len } if index == 0 => {
+            let reply = unsafe { view::<ICMPV6_ECHO_REPLY>(area, 0)? };
+            let data = data_at(area, size_of::<ICMPV6_ECHO_REPLY>(), data_len);
+            Some(Reply {
+                addr: IpAddr::V6(rust_ipv6(reply.Address.sin6_addr)),
+                status: reply.Status,
+                rtt: reply.RoundTripTime as u32,
+                ttl: None,
+                data,
+            })
+        }
+        _ => None,
+    }
+}
+/// Views the reply area as the index'th element of an array of T.
+///
+/// Safety: T must be one of the ICMP(V6)_ECHO_REPLY(32) types, which are all Copy
+/// and have an alignment no greater than CHUNK_SIZE (see the asserts at the top).
+unsafe fn view<T>(area: &[Chunk], index: usize) -> Option<&T> {
+    let end = index.checked_add(1)?.checked_mul(size_of::<T>())?;
+    if area.len() * CHUNK_SIZE >= end {
+        Some(&*area.as_ptr().cast::<T>().add(index))
+    } else {
+        None
+    }
+}
+fn data_at(area: &[Chunk], offset: usize, len: usize) -> &[u8] {
+    // Safety: Chunk is a plain array of u8
+    let bytes =
+        unsafe { std::slice::from_raw_parts(area.as_ptr().cast::<u8>(), area.len() * CHUNK_SIZE) };
+    offset
+        .checked_add(len)
+        .and_then(|end| bytes.get(offset..end))
+        .unwrap_or(&[])
+}
+
+#[test]
+fn multiple_replies_data() {
+    let mut buf = Buffer::with_data(vec![0; 4]);
+    buf.set_max_replies(2);
+    buf.init_for_send();
+    let area = buf.reply_data_ptr() as *mut u8;
+    // Two reply structures, followed by their data
+    let data_offset = 2 * size_of::<ICMP_ECHO_REPLY>();
+    for (index, data) in [*b"abcd", *b"efgh"].iter().enumerate() {
+        let data_ptr = unsafe { area.add(data_offset + index * data.len()) };
+        let reply = ICMP_ECHO_REPLY {
+            Status: IP_SUCCESS,
+            DataSize: data.len() as u16,
+            Data: data_ptr as *mut VOID,
+            ..Default::default()
+        };
+        unsafe {
+            (area as *mut ICMP_ECHO_REPLY).add(index).write(reply);
+            data_ptr.copy_from_nonoverlapping(data.as_ptr(), data.len());
+        }
+    }
+    buf.set_filled4(2);
+    let data: Vec<_> = buf.replies().map(|reply| reply.data().to_vec()).collect();
+    assert_eq!(data, [b"abcd".to_vec(), b"efgh".to_vec()]);
+    assert_eq!(buf.reply_data(), b"abcd");
+}
+#[test]
+fn reply_area_size_fits_any_alignment() {
+    for data_len in 0..=CHUNK_SIZE {
+        let size = reply_area_size(data_len);
+        let mut bytes = vec![Chunk([0; CHUNK_SIZE]); chunks(size) + 1];
+        // Safety: Chunk is a plain array of u8
+        let bytes = unsafe { bytes.align_to_mut::<u8>().1 };
+        for offset in 0..CHUNK_SIZE {
+            let area = ReplyArea::new(&mut bytes[offset..offset + size]);
+            assert!(
+                area.fits(data_len),
+                "data_len {} offset {}",
+                data_len,
+                offset
+            );
+        }
+    }
 }
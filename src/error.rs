@@ -2,25 +2,46 @@ use winapi::{
     shared::{
         ntdef::NULL,
         winerror::{
-            ERROR_HOST_UNREACHABLE, ERROR_NETWORK_UNREACHABLE, ERROR_PROTOCOL_UNREACHABLE, NO_ERROR,
+            ERROR_HOST_UNREACHABLE, ERROR_NETWORK_UNREACHABLE, ERROR_NOT_ENOUGH_MEMORY,
+            ERROR_NO_SYSTEM_RESOURCES, ERROR_OUTOFMEMORY, ERROR_PORT_UNREACHABLE,
+            ERROR_PROTOCOL_UNREACHABLE, NO_ERROR, WSAEHOSTUNREACH, WSAENETUNREACH, WSAENOBUFS,
+            WSAETIMEDOUT,
         },
     },
     um::{
-        errhandlingapi::GetLastError,
         ipexport::{
-            IP_DEST_HOST_UNREACHABLE, IP_DEST_NET_UNREACHABLE, IP_DEST_PROT_UNREACHABLE,
-            IP_PACKET_TOO_BIG, IP_REQ_TIMED_OUT, IP_STATUS_BASE, IP_TTL_EXPIRED_REASSEM,
-            IP_TTL_EXPIRED_TRANSIT, MAX_IP_STATUS,
+            IP_BAD_DESTINATION, IP_BAD_HEADER, IP_BAD_OPTION, IP_BAD_REQ, IP_BAD_ROUTE,
+            IP_BUF_TOO_SMALL, IP_DEST_ADDR_UNREACHABLE, IP_DEST_HOST_UNREACHABLE,
+            IP_DEST_NET_UNREACHABLE, IP_DEST_NO_ROUTE, IP_DEST_PORT_UNREACHABLE,
+            IP_DEST_PROHIBITED, IP_DEST_PROT_UNREACHABLE, IP_DEST_SCOPE_MISMATCH,
+            IP_DEST_UNREACHABLE, IP_GENERAL_FAILURE, IP_HOP_LIMIT_EXCEEDED, IP_HW_ERROR,
+            IP_ICMP_ERROR, IP_NEGOTIATING_IPSEC, IP_NO_RESOURCES, IP_OPTION_TOO_BIG,
+            IP_PACKET_TOO_BIG, IP_PARAMETER_PROBLEM, IP_PARAM_PROBLEM, IP_REASSEMBLY_TIME_EXCEEDED,
+            IP_REQ_TIMED_OUT, IP_SOURCE_QUENCH, IP_STATUS_BASE, IP_TIME_EXCEEDED,
+            IP_TTL_EXPIRED_REASSEM, IP_TTL_EXPIRED_TRANSIT, IP_UNRECOGNIZED_NEXT_HEADER,
+            MAX_IP_STATUS,
         },
         iphlpapi::GetIpErrorString,
         winbase::{FormatMessageW, FORMAT_MESSAGE_FROM_SYSTEM, FORMAT_MESSAGE_IGNORE_INSERTS},
     },
 };
 
-use std::fmt::{self, Debug, Display, Formatter};
+use std::{
+    fmt::{self, Debug, Display, Formatter},
+    io,
+};
 
 /// An error when sending a ping request.
+///
+/// Some errors only occur for one IP version. Where Windows reports the same status
+/// for different ICMP errors in IPv4 and IPv6, there is a variant for each, so every
+/// variant maps to at most one ICMP type and code per IP version. See
+/// [`Error::icmpv4_type_code`] and [`Error::icmpv6_type_code`].
+///
+/// Variants may be added for statuses which are currently reported as [`Error::Other`],
+/// so matches need a wildcard arm.
 #[derive(Copy, Clone, Eq, PartialEq, Hash)]
+#[non_exhaustive]
 pub enum Error {
     /// The ping request timed out.
     Timeout,
@@ -28,14 +49,61 @@ pub enum Error {
     NetUnreachable,
     /// The destination host is unreachable.
     HostUnreachable,
-    /// The IP TTL expired during transit.
+    /// The IP TTL expired during transit. For IPv6, see `HopLimitExceeded`.
     TtlExpired,
     /// The IP reassembly timer expired.
     ReassemblyExpired,
-    /// The packet needs fragmented, but the DF bit is set.
+    /// The packet needs fragmented, but the DF bit is set. For IPv6, the packet is too big.
     NeedsFragmented,
     /// The destination protocol is unreachable.
     ProtocolUnreachable,
+    /// The destination port is unreachable.
+    PortUnreachable,
+    /// The reply buffer was too small.
+    BufferTooSmall,
+    /// There were not enough resources to complete the request.
+    NoResources,
+    /// A bad IP option was specified.
+    BadOption,
+    /// A hardware error occurred.
+    HardwareError,
+    /// The request was invalid.
+    BadRequest,
+    /// The route was bad, eg a source route failed.
+    BadRoute,
+    /// A parameter problem was reported.
+    ParameterProblem,
+    /// The request was throttled by a source quench.
+    SourceQuench,
+    /// The IP options were too big.
+    OptionTooBig,
+    /// The destination was bad.
+    BadDestination,
+    /// (IPv6) There is no route to the destination.
+    NoRoute,
+    /// (IPv6) The destination address is unreachable.
+    AddrUnreachable,
+    /// (IPv6) Communication with the destination is administratively prohibited,
+    /// eg by a firewall filter.
+    AdminProhibited,
+    /// (IPv6) The IP hop limit was exceeded during transit.
+    HopLimitExceeded,
+    /// (IPv6) The destination is beyond the scope of the source address.
+    ScopeMismatch,
+    /// (IPv6) The IP header was bad.
+    BadHeader,
+    /// (IPv6) The next header was not recognized.
+    UnrecognizedNextHeader,
+    /// The destination is unreachable, for some reason not covered by another variant.
+    DestUnreachable,
+    /// Some time was exceeded, for some reason not covered by another variant.
+    TimeExceeded,
+    /// Some other ICMP error was received.
+    IcmpError,
+    /// IPsec is still being negotiated.
+    NegotiatingIpsec,
+    /// A general failure occurred, eg a malformed ICMP packet was received.
+    GeneralFailure,
     /// Some other error ocurred. Format with debug or diplay to get more info.
     Other(u32),
 }
@@ -50,11 +118,44 @@ impl Error {
             IP_TTL_EXPIRED_REASSEM => Error::ReassemblyExpired,
             IP_DEST_PROT_UNREACHABLE => Error::ProtocolUnreachable,
             IP_PACKET_TOO_BIG => Error::NeedsFragmented,
+            IP_DEST_PORT_UNREACHABLE => Error::PortUnreachable,
+            IP_BUF_TOO_SMALL => Error::BufferTooSmall,
+            IP_NO_RESOURCES => Error::NoResources,
+            IP_BAD_OPTION => Error::BadOption,
+            IP_HW_ERROR => Error::HardwareError,
+            IP_BAD_REQ => Error::BadRequest,
+            IP_BAD_ROUTE => Error::BadRoute,
+            IP_PARAM_PROBLEM => Error::ParameterProblem,
+            IP_SOURCE_QUENCH => Error::SourceQuench,
+            IP_OPTION_TOO_BIG => Error::OptionTooBig,
+            IP_BAD_DESTINATION => Error::BadDestination,
+            IP_DEST_UNREACHABLE => Error::DestUnreachable,
+            IP_TIME_EXCEEDED => Error::TimeExceeded,
+            IP_ICMP_ERROR => Error::IcmpError,
+            IP_NEGOTIATING_IPSEC => Error::NegotiatingIpsec,
+            IP_GENERAL_FAILURE => Error::GeneralFailure,
             _ => Error::Other(err),
         }
     }
+    /// Like from_iperror, but for statuses which have a different meaning for IPv6.
+    pub(crate) fn from_iperror6(err: u32) -> Self {
+        match err {
+            IP_DEST_NO_ROUTE => Error::NoRoute,
+            IP_DEST_ADDR_UNREACHABLE => Error::AddrUnreachable,
+            IP_DEST_PROHIBITED => Error::AdminProhibited,
+            IP_HOP_LIMIT_EXCEEDED => Error::HopLimitExceeded,
+            IP_REASSEMBLY_TIME_EXCEEDED => Error::ReassemblyExpired,
+            IP_PARAMETER_PROBLEM => Error::ParameterProblem,
+            IP_DEST_SCOPE_MISMATCH => Error::ScopeMismatch,
+            IP_BAD_HEADER => Error::BadHeader,
+            IP_UNRECOGNIZED_NEXT_HEADER => Error::UnrecognizedNextHeader,
+            _ => Error::from_iperror(err),
+        }
+    }
     /// Creates an Error from the last Windows error
+    #[cfg(feature = "async")]
     pub(crate) fn from_lasterror() -> Self {
+        use winapi::um::errhandlingapi::GetLastError;
         Self::from_winerror(unsafe { GetLastError() })
     }
     /// Takes either a Windows error or IP_STATUS value
@@ -64,11 +165,317 @@ impl Error {
             ERROR_HOST_UNREACHABLE => Error::HostUnreachable,
             ERROR_NETWORK_UNREACHABLE => Error::NetUnreachable,
             ERROR_PROTOCOL_UNREACHABLE => Error::ProtocolUnreachable,
+            ERROR_PORT_UNREACHABLE => Error::PortUnreachable,
+            WSAETIMEDOUT => Error::Timeout,
+            WSAEHOSTUNREACH => Error::HostUnreachable,
+            WSAENETUNREACH => Error::NetUnreachable,
             _ => Error::Other(err),
         }
     }
+    /// Takes either a Windows error or IP_STATUS value, after an IPv6 request
+    pub(crate) fn from_winerror6(err: u32) -> Self {
+        match err {
+            IP_STATUS_BASE..=MAX_IP_STATUS => Error::from_iperror6(err),
+            _ => Error::from_winerror(err),
+        }
+    }
+    /// Gets the ICMPv4 type and code which this error is reported with, if any.
+    pub fn icmpv4_type_code(&self) -> Option<(u8, u8)> {
+        Some(match self {
+            Error::NetUnreachable => (3, 0),
+            Error::HostUnreachable => (3, 1),
+            Error::ProtocolUnreachable => (3, 2),
+            Error::PortUnreachable => (3, 3),
+            Error::NeedsFragmented => (3, 4),
+            Error::BadRoute => (3, 5),
+            Error::SourceQuench => (4, 0),
+            Error::TtlExpired => (11, 0),
+            Error::ReassemblyExpired => (11, 1),
+            Error::ParameterProblem => (12, 0),
+            _ => return None,
+        })
+    }
+    /// Gets the ICMPv6 type and code which this error is reported with, if any.
+    pub fn icmpv6_type_code(&self) -> Option<(u8, u8)> {
+        Some(match self {
+            Error::NoRoute => (1, 0),
+            Error::AdminProhibited => (1, 1),
+            Error::ScopeMismatch => (1, 2),
+            Error::AddrUnreachable => (1, 3),
+            Error::PortUnreachable => (1, 4),
+            Error::NeedsFragmented => (2, 0),
+            Error::HopLimitExceeded => (3, 0),
+            Error::ReassemblyExpired => (3, 1),
+            Error::ParameterProblem | Error::BadHeader => (4, 0),
+            Error::UnrecognizedNextHeader => (4, 1),
+            _ => return None,
+        })
+    }
+    /// Gets the error for an ICMPv4 error message with the given type and code. Codes without
+    /// their own variant fall back to the general one for the type, eg `DestUnreachable`.
+    /// Returns None if the type is not an error.
+    pub fn from_icmpv4_type_code(icmp_type: u8, code: u8) -> Option<Self> {
+        let known = CODED
+            .iter()
+            .copied()
+            .find(|err| err.icmpv4_type_code() == Some((icmp_type, code)));
+        known.or(match icmp_type {
+            3 => Some(Error::DestUnreachable),
+            4 => Some(Error::SourceQuench),
+            11 => Some(Error::TimeExceeded),
+            12 => Some(Error::ParameterProblem),
+            _ => None,
+        })
+    }
+    /// Gets the error for an ICMPv6 error message with the given type and code. Codes without
+    /// their own variant fall back to the general one for the type, eg `DestUnreachable`.
+    /// Returns None if the type is not an error.
+    pub fn from_icmpv6_type_code(icmp_type: u8, code: u8) -> Option<Self> {
+        let known = CODED
+            .iter()
+            .copied()
+            .find(|err| err.icmpv6_type_code() == Some((icmp_type, code)));
+        known.or(match icmp_type {
+            1 => Some(Error::DestUnreachable),
+            2 => Some(Error::NeedsFragmented),
+            3 => Some(Error::TimeExceeded),
+            4 => Some(Error::ParameterProblem),
+            _ => None,
+        })
+    }
 }
 
+impl Error {
+    /// Gets a stable, machine readable code for this error, such as `"timeout"` or
+    /// `"admin_prohibited"`. These codes will not change between versions, so are
+    /// suitable for logging and matching on. For `Error::Other`, this is `"other"`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Timeout => "timeout",
+            Error::HostUnreachable => "host_unreachable",
+            Error::NetUnreachable => "net_unreachable",
+            Error::TtlExpired => "ttl_expired",
+            Error::ReassemblyExpired => "reassembly_expired",
+            Error::NeedsFragmented => "needs_fragmented",
+            Error::ProtocolUnreachable => "protocol_unreachable",
+            Error::PortUnreachable => "port_unreachable",
+            Error::BufferTooSmall => "buffer_too_small",
+            Error::NoResources => "no_resources",
+            Error::BadOption => "bad_option",
+            Error::HardwareError => "hardware_error",
+            Error::BadRequest => "bad_request",
+            Error::BadRoute => "bad_route",
+            Error::ParameterProblem => "parameter_problem",
+            Error::SourceQuench => "source_quench",
+            Error::OptionTooBig => "option_too_big",
+            Error::BadDestination => "bad_destination",
+            Error::NoRoute => "no_route",
+            Error::AddrUnreachable => "addr_unreachable",
+            Error::AdminProhibited => "admin_prohibited",
+            Error::HopLimitExceeded => "hop_limit_exceeded",
+            Error::ScopeMismatch => "scope_mismatch",
+            Error::BadHeader => "bad_header",
+            Error::UnrecognizedNextHeader => "unrecognized_next_header",
+            Error::DestUnreachable => "dest_unreachable",
+            Error::TimeExceeded => "time_exceeded",
+            Error::IcmpError => "icmp_error",
+            Error::NegotiatingIpsec => "negotiating_ipsec",
+            Error::GeneralFailure => "general_failure",
+            Error::Other(_) => "other",
+        }
+    }
+    /// Gets the error with the given code, as returned by [`Error::code`]. Returns None
+    /// for unknown codes and for `"other"`, which doesn't identify a single error.
+    pub fn from_code(code: &str) -> Option<Self> {
+        CODED.iter().copied().find(|err| err.code() == code)
+    }
+    /// Gets a version of this error which displays the OS's message where there is one.
+    /// Unlike the built-in messages used by Display and Debug, this is in the user's UI
+    /// language.
+    pub fn localized(self) -> Localized {
+        Localized(self)
+    }
+    /// Gets the closest matching io::ErrorKind for this error.
+    pub fn kind(&self) -> io::ErrorKind {
+        use io::ErrorKind;
+        match self {
+            Error::Timeout => ErrorKind::TimedOut,
+            // ErrorKind::HostUnreachable and NetworkUnreachable need Rust 1.83, so these go
+            // through the OS error, which std maps to them where they exist
+            Error::HostUnreachable | Error::AddrUnreachable => {
+                io::Error::from_raw_os_error(WSAEHOSTUNREACH as i32).kind()
+            }
+            Error::NetUnreachable | Error::NoRoute => {
+                io::Error::from_raw_os_error(WSAENETUNREACH as i32).kind()
+            }
+            Error::PortUnreachable => ErrorKind::ConnectionRefused,
+            Error::AdminProhibited => ErrorKind::PermissionDenied,
+            Error::NoResources => ErrorKind::OutOfMemory,
+            Error::BadRequest | Error::BadOption | Error::OptionTooBig => ErrorKind::InvalidInput,
+            Error::Other(err) => io::Error::from_raw_os_error(*err as i32).kind(),
+            _ => ErrorKind::Other,
+        }
+    }
+    /// Checks whether this error is likely to go away if the request is retried, such as
+    /// a timeout or a shortage of resources. Errors reported by the network about the
+    /// destination, such as `NetUnreachable`, are permanent.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Timeout
+            | Error::NoResources
+            | Error::SourceQuench
+            | Error::HardwareError
+            | Error::NegotiatingIpsec
+            | Error::GeneralFailure => true,
+            Error::Other(err) => matches!(
+                *err,
+                ERROR_NOT_ENOUGH_MEMORY
+                    | ERROR_OUTOFMEMORY
+                    | ERROR_NO_SYSTEM_RESOURCES
+                    | WSAENOBUFS
+            ),
+            _ => false,
+        }
+    }
+    /// Gets the IP_STATUS value Windows reports for this error.
+    fn ip_status(self) -> Option<u32> {
+        Some(match self {
+            Error::Timeout => IP_REQ_TIMED_OUT,
+            Error::NetUnreachable => IP_DEST_NET_UNREACHABLE,
+            Error::HostUnreachable => IP_DEST_HOST_UNREACHABLE,
+            Error::TtlExpired => IP_TTL_EXPIRED_TRANSIT,
+            Error::ReassemblyExpired => IP_TTL_EXPIRED_REASSEM,
+            Error::NeedsFragmented => IP_PACKET_TOO_BIG,
+            Error::ProtocolUnreachable => IP_DEST_PROT_UNREACHABLE,
+            Error::PortUnreachable => IP_DEST_PORT_UNREACHABLE,
+            Error::BufferTooSmall => IP_BUF_TOO_SMALL,
+            Error::NoResources => IP_NO_RESOURCES,
+            Error::BadOption => IP_BAD_OPTION,
+            Error::HardwareError => IP_HW_ERROR,
+            Error::BadRequest => IP_BAD_REQ,
+            Error::BadRoute => IP_BAD_ROUTE,
+            Error::ParameterProblem => IP_PARAM_PROBLEM,
+            Error::SourceQuench => IP_SOURCE_QUENCH,
+            Error::OptionTooBig => IP_OPTION_TOO_BIG,
+            Error::BadDestination => IP_BAD_DESTINATION,
+            Error::NoRoute => IP_DEST_NO_ROUTE,
+            Error::AddrUnreachable => IP_DEST_ADDR_UNREACHABLE,
+            Error::AdminProhibited => IP_DEST_PROHIBITED,
+            Error::HopLimitExceeded => IP_HOP_LIMIT_EXCEEDED,
+            Error::ScopeMismatch => IP_DEST_SCOPE_MISMATCH,
+            Error::BadHeader => IP_BAD_HEADER,
+            Error::UnrecognizedNextHeader => IP_UNRECOGNIZED_NEXT_HEADER,
+            Error::DestUnreachable => IP_DEST_UNREACHABLE,
+            Error::TimeExceeded => IP_TIME_EXCEEDED,
+            Error::IcmpError => IP_ICMP_ERROR,
+            Error::NegotiatingIpsec => IP_NEGOTIATING_IPSEC,
+            Error::GeneralFailure => IP_GENERAL_FAILURE,
+            Error::Other(err @ IP_STATUS_BASE..=MAX_IP_STATUS) => err,
+            Error::Other(_) => return None,
+        })
+    }
+}
+
+/// Built-in messages for IP_STATUS values and common Windows errors, so formatting
+/// does not depend on the OS or the user's UI language.
+fn message(err: u32) -> Option<&'static str> {
+    Some(match err {
+        0 => "The operation completed successfully.",
+        5 => "Access is denied.",
+        6 => "The handle is invalid.",
+        8 => "Not enough memory resources are available to process this command.",
+        14 => "Not enough memory resources are available to complete this operation.",
+        31 => "A device attached to the system is not functioning.",
+        50 => "The request is not supported.",
+        87 => "The parameter is incorrect.",
+        122 => "The data area passed to a system call is too small.",
+        995 => "The I/O operation has been aborted.",
+        997 => "Overlapped I/O operation is in progress.",
+        1168 => "Element not found.",
+        1231 => "The network location cannot be reached.",
+        1232 => "The remote system is not reachable by the transport.",
+        1233 => "The remote system does not support the transport protocol.",
+        1234 => "No service is operating at the destination network endpoint on the remote system.",
+        1460 => "This operation returned because the timeout period expired.",
+        11001 => "Buffer too small.",
+        11002 => "Destination net unreachable.",
+        11003 => "Destination host unreachable.",
+        11004 => "Destination protocol unreachable.",
+        11005 => "Destination port unreachable.",
+        11006 => "No resources.",
+        11007 => "Bad option specified.",
+        11008 => "Hardware error.",
+        11009 => "Packet too big.",
+        11010 => "Request timed out.",
+        11011 => "Bad request.",
+        11012 => "Bad route.",
+        11013 => "TTL expired in transit.",
+        11014 => "TTL expired during reassembly.",
+        11015 => "Parameter problem.",
+        11016 => "Source quench received.",
+        11017 => "Option too big.",
+        11018 => "Bad destination.",
+        11019 => "Address deleted.",
+        11020 => "Spec MTU change.",
+        11021 => "MTU change.",
+        11022 => "Unloaded.",
+        11023 => "Address added.",
+        11024 => "Media connect.",
+        11025 => "Media disconnect.",
+        11026 => "Bind adapter.",
+        11027 => "Unbind adapter.",
+        11028 => "Device does not exist.",
+        11029 => "Duplicate address.",
+        11030 => "Interface metric change.",
+        11031 => "Reconfig security filter.",
+        11032 => "Negotiating IPSEC.",
+        11033 => "Interface wake-on-LAN capability change.",
+        11034 => "Duplicate IP address.",
+        11040 => "Destination unreachable.",
+        11041 => "Time exceeded.",
+        11042 => "Bad header.",
+        11043 => "Unrecognized next header.",
+        11044 => "ICMP error.",
+        11045 => "Destination scope mismatch.",
+        11050 => "General failure.",
+        _ => return None,
+    })
+}
+
+// Every error with its own code, ie all but Error::Other.
+const CODED: [Error; 30] = [
+    Error::Timeout,
+    Error::NetUnreachable,
+    Error::HostUnreachable,
+    Error::TtlExpired,
+    Error::ReassemblyExpired,
+    Error::NeedsFragmented,
+    Error::ProtocolUnreachable,
+    Error::PortUnreachable,
+    Error::BufferTooSmall,
+    Error::NoResources,
+    Error::BadOption,
+    Error::HardwareError,
+    Error::BadRequest,
+    Error::BadRoute,
+    Error::ParameterProblem,
+    Error::SourceQuench,
+    Error::OptionTooBig,
+    Error::BadDestination,
+    Error::NoRoute,
+    Error::AddrUnreachable,
+    Error::AdminProhibited,
+    Error::HopLimitExceeded,
+    Error::ScopeMismatch,
+    Error::BadHeader,
+    Error::UnrecognizedNextHeader,
+    Error::DestUnreachable,
+    Error::TimeExceeded,
+    Error::IcmpError,
+    Error::NegotiatingIpsec,
+    Error::GeneralFailure,
+];
+
 impl Debug for Error {
     fn fmt(&self, out: &mut Formatter) -> fmt::Result {
         match self {
@@ -79,23 +486,113 @@ impl Debug for Error {
             Error::ReassemblyExpired => write!(out, "Reassembly timed out waiting for fragments"),
             Error::NeedsFragmented => write!(out, "Packet needs fragmented"),
             Error::ProtocolUnreachable => write!(out, "Destination protocol unreachable"),
-            Error::Other(err @ IP_STATUS_BASE..=MAX_IP_STATUS) => {
+            Error::PortUnreachable => write!(out, "Destination port unreachable"),
+            Error::BufferTooSmall => write!(out, "Buffer too small"),
+            Error::NoResources => write!(out, "No resources"),
+            Error::BadOption => write!(out, "Bad option"),
+            Error::HardwareError => write!(out, "Hardware error"),
+            Error::BadRequest => write!(out, "Bad request"),
+            Error::BadRoute => write!(out, "Bad route"),
+            Error::ParameterProblem => write!(out, "Parameter problem"),
+            Error::SourceQuench => write!(out, "Source quench received"),
+            Error::OptionTooBig => write!(out, "Option too big"),
+            Error::BadDestination => write!(out, "Bad destination"),
+            Error::NoRoute => write!(out, "No route to destination"),
+            Error::AddrUnreachable => write!(out, "Destination address unreachable"),
+            Error::AdminProhibited => write!(out, "Communication administratively prohibited"),
+            Error::HopLimitExceeded => write!(out, "Hop limit exceeded in transit"),
+            Error::ScopeMismatch => write!(out, "Destination scope does not match source"),
+            Error::BadHeader => write!(out, "Bad IP header"),
+            Error::UnrecognizedNextHeader => write!(out, "Unrecognized next header"),
+            Error::DestUnreachable => write!(out, "Destination unreachable"),
+            Error::TimeExceeded => write!(out, "Time exceeded"),
+            Error::IcmpError => write!(out, "ICMP error"),
+            Error::NegotiatingIpsec => write!(out, "Negotiating IPsec"),
+            Error::GeneralFailure => write!(out, "General failure"),
+            Error::Other(err @ IP_STATUS_BASE..=MAX_IP_STATUS) => match message(*err) {
+                Some(s) => write!(out, "Other IP error ({}): {}", err, s),
+                None => write!(out, "Other IP error ({})", err),
+            },
+            Error::Other(err) => match message(*err) {
+                Some(s) => write!(out, "Other error ({}): {}", err, s),
+                None => write!(out, "Other error ({})", err),
+            },
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        Debug::fmt(self, out)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Converts to an io::Error with a matching kind (see [`Error::kind`]). The original
+/// Error can be recovered with `get_ref` and `downcast_ref`, or by converting back.
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        io::Error::new(err.kind(), err)
+    }
+}
+
+/// Converts from an io::Error. If the io::Error was converted from an Error, the original
+/// Error is returned. Otherwise the OS error code is used if there is one, falling back
+/// on the error kind.
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        if let Some(err) = err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+            return *err;
+        }
+        if let Some(code) = err.raw_os_error() {
+            return Error::from_winerror(code as u32);
+        }
+        match err.kind() {
+            io::ErrorKind::TimedOut => Error::Timeout,
+            io::ErrorKind::ConnectionRefused => Error::PortUnreachable,
+            io::ErrorKind::OutOfMemory => Error::NoResources,
+            _ => Error::GeneralFailure,
+        }
+    }
+}
+
+/// An [`Error`] which displays the OS's localized message, as returned by [`Error::localized`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Localized(Error);
+
+impl Localized {
+    /// Gets the underlying error.
+    pub fn error(&self) -> Error {
+        self.0
+    }
+}
+
+impl Debug for Localized {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        match (self.0, self.0.ip_status()) {
+            (err, Some(status)) => {
                 let mut buf = [0u16; 256];
                 let ret =
-                    unsafe { GetIpErrorString(*err, &mut buf[0], &mut (buf.len() as u32 - 1)) };
-                debug_assert_eq!(NO_ERROR, ret);
+                    unsafe { GetIpErrorString(status, &mut buf[0], &mut (buf.len() as u32 - 1)) };
+                if ret != NO_ERROR {
+                    return Debug::fmt(&err, out);
+                }
                 let len = buf.iter().take_while(|x| **x != 0).count();
                 let s = String::from_utf16_lossy(&buf[..len]);
-                write!(out, "Other IP error ({}): {}", err, s.trim())
+                match err {
+                    Error::Other(_) => write!(out, "Other IP error ({}): {}", status, s.trim()),
+                    _ => write!(out, "{}", s.trim()),
+                }
             }
-            Error::Other(err) => {
+            (Error::Other(err), None) => {
                 const FLAGS: u32 = FORMAT_MESSAGE_FROM_SYSTEM | FORMAT_MESSAGE_IGNORE_INSERTS;
                 let mut buf = [0u16; 256];
                 let len = unsafe {
                     FormatMessageW(
                         FLAGS,
                         NULL,
-                        *err,
+                        err,
                         0, // lang id
                         &mut buf[0],
                         buf.len() as u32,
@@ -105,14 +602,49 @@ impl Debug for Error {
                 let s = String::from_utf16_lossy(&buf[..len as usize]);
                 write!(out, "Other error ({}): {}", err, s.trim())
             }
+            (err, None) => Debug::fmt(&err, out),
         }
     }
 }
 
-impl Display for Error {
+impl Display for Localized {
     fn fmt(&self, out: &mut Formatter) -> fmt::Result {
         Debug::fmt(self, out)
     }
 }
 
-impl std::error::Error for Error {}
+// Errors are serialized by their code, eg "timeout", except Error::Other which
+// keeps its error number as {"other": n}.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum ErrorRepr<'a> {
+    Code(std::borrow::Cow<'a, str>),
+    Other { other: u32 },
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Error {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Error::Other(other) => ErrorRepr::Other { other },
+            err => ErrorRepr::Code(err.code().into()),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Error {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match ErrorRepr::deserialize(deserializer)? {
+            ErrorRepr::Code(code) => Error::from_code(&code).ok_or_else(|| {
+                serde::de::Error::invalid_value(
+                    serde::de::Unexpected::Str(&code),
+                    &"an error code such as \"timeout\"",
+                )
+            }),
+            ErrorRepr::Other { other } => Ok(Error::Other(other)),
+        }
+    }
+}
@@ -0,0 +1,500 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// Parsing of ICMP error messages (Destination Unreachable, Time Exceeded, etc),
+// including multi-part extensions (RFC 4884) such as MPLS label stacks (RFC 4950)
+// and interface information (RFC 5837).
+//
+// The Windows ICMP API (IcmpSendEcho and friends) only reports the status and the
+// address of the host which sent an error, not the error message itself. These types
+// are for callers which get the raw bytes some other way, such as a packet capture.
+
+const PROTO_ICMP: u8 = 1;
+const PROTO_ICMPV6: u8 = 58;
+const ECHO_REQUEST: u8 = 8;
+const ECHO_REQUEST_V6: u8 = 128;
+
+/// An ICMP or ICMPv6 error message, the datagram it quotes, and any
+/// multi-part extensions (RFC 4884).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct IcmpErrorMessage<'a> {
+    icmp_type: u8,
+    code: u8,
+    quoted: QuotedDatagram<'a>,
+    extensions: &'a [u8],
+}
+
+impl<'a> IcmpErrorMessage<'a> {
+    /// Parses an ICMPv4 error message, starting at the ICMP header (not the IP header).
+    /// Returns None if the message is not an error, or is too short.
+    pub fn parse_v4(bytes: &'a [u8]) -> Option<Self> {
+        let (&icmp_type, &code) = (bytes.first()?, bytes.get(1)?);
+        // Destination Unreachable, Source Quench, Redirect, Time Exceeded, Parameter Problem
+        if ![3, 4, 5, 11, 12].contains(&icmp_type) {
+            return None;
+        }
+        // RFC 4884 length is in 32 bit words, for the types which support extensions
+        let length = match icmp_type {
+            3 | 11 | 12 => *bytes.get(5)? as usize * 4,
+            _ => 0,
+        };
+        Self::parse(icmp_type, code, bytes.get(8..)?, length)
+    }
+    /// Parses an ICMPv6 error message, starting at the ICMPv6 header (not the IP header).
+    /// Returns None if the message is not an error, or is too short.
+    pub fn parse_v6(bytes: &'a [u8]) -> Option<Self> {
+        let (&icmp_type, &code) = (bytes.first()?, bytes.get(1)?);
+        // Error messages have types 0 to 127, 1 to 4 are defined by RFC 4443
+        if icmp_type >= 128 {
+            return None;
+        }
+        // RFC 4884 length is in 64 bit words, for the types which support extensions
+        let length = match icmp_type {
+            1 | 3 => *bytes.get(4)? as usize * 8,
+            _ => 0,
+        };
+        Self::parse(icmp_type, code, bytes.get(8..)?, length)
+    }
+    fn parse(icmp_type: u8, code: u8, body: &'a [u8], length: usize) -> Option<Self> {
+        let (quoted, extensions) = if length != 0 && length <= body.len() {
+            body.split_at(length)
+        } else if length == 0 && body.len() > 128 && is_extension_header(&body[128..]) {
+            // RFC 4884 section 5: non-compliant (eg RFC 4950 era) implementations
+            // quote exactly 128 bytes and leave the length field zero.
+            body.split_at(128)
+        } else {
+            (body, &[][..])
+        };
+        Some(Self {
+            icmp_type,
+            code,
+            quoted: QuotedDatagram::parse(quoted)?,
+            extensions,
+        })
+    }
+    /// Gets the ICMP type.
+    pub fn icmp_type(&self) -> u8 {
+        self.icmp_type
+    }
+    /// Gets the ICMP code.
+    pub fn code(&self) -> u8 {
+        self.code
+    }
+    /// Gets the datagram quoted by this error.
+    pub fn quoted(&self) -> &QuotedDatagram<'a> {
+        &self.quoted
+    }
+    /// Gets the [`Error`](crate::Error) this message reports, from its type and code.
+    pub fn error(&self) -> crate::Error {
+        let error = match self.quoted.ip() {
+            IpHeader::V4(_) => crate::Error::from_icmpv4_type_code(self.icmp_type, self.code),
+            IpHeader::V6(_) => crate::Error::from_icmpv6_type_code(self.icmp_type, self.code),
+        };
+        // ICMPv6 error types without a meaning of their own
+        error.unwrap_or(crate::Error::IcmpError)
+    }
+    /// Gets the extension objects appended to this error, such as MPLS label stacks
+    /// (RFC 4950) and interface information (RFC 5837). Returns an empty Vec if there
+    /// are no extensions, or the extension structure is malformed.
+    ///
+    /// This is a standalone parser. [`Error::TtlExpired`](crate::Error::TtlExpired) from a
+    /// [`Pinger`](crate::Pinger) or [`Buffer`](crate::Buffer) has no extensions, as
+    /// IcmpSendEcho doesn't expose the bytes of the error message, so traceroute hops found
+    /// with it can't show MPLS tunnels. The bytes must come from elsewhere, eg a packet capture.
+    pub fn extensions(&self) -> Vec<IcmpExtension<'a>> {
+        if !is_extension_header(self.extensions) {
+            return Vec::new();
+        }
+        let mut ret = Vec::new();
+        let mut rest = &self.extensions[4..];
+        while rest.len() >= 4 {
+            let len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+            if len < 4 || len > rest.len() {
+                break;
+            }
+            ret.push(IcmpExtension::parse(rest[2], rest[3], &rest[4..len]));
+            rest = &rest[len..];
+        }
+        ret
+    }
+}
+
+fn is_extension_header(bytes: &[u8]) -> bool {
+    // Version 2 is the only version defined by RFC 4884
+    bytes.len() >= 4 && bytes[0] >> 4 == 2
+}
+
+/// An ICMP extension object (RFC 4884).
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum IcmpExtension<'a> {
+    /// An MPLS label stack (RFC 4950), outermost label first.
+    Mpls(Vec<MplsLabel>),
+    /// Information about an interface of the hop which sent the error (RFC 5837).
+    Interface(InterfaceInfo<'a>),
+    /// Some other, or a malformed, extension object.
+    Unknown {
+        class_num: u8,
+        c_type: u8,
+        data: &'a [u8],
+    },
+}
+
+impl<'a> IcmpExtension<'a> {
+    fn parse(class_num: u8, c_type: u8, data: &'a [u8]) -> Self {
+        let parsed = match (class_num, c_type) {
+            (1, 1) if data.len() % 4 == 0 => Some(IcmpExtension::Mpls(
+                data.chunks(4).map(MplsLabel::parse).collect(),
+            )),
+            (2, _) => InterfaceInfo::parse(c_type, data).map(IcmpExtension::Interface),
+            _ => None,
+        };
+        parsed.unwrap_or(IcmpExtension::Unknown {
+            class_num,
+            c_type,
+            data,
+        })
+    }
+}
+
+/// One entry of an MPLS label stack (RFC 4950).
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MplsLabel {
+    /// The 20 bit label.
+    pub label: u32,
+    /// The 3 bit experimental use (traffic class) field.
+    pub exp: u8,
+    /// The bottom of stack bit.
+    pub s: bool,
+    /// The MPLS TTL.
+    pub ttl: u8,
+}
+
+impl MplsLabel {
+    fn parse(bytes: &[u8]) -> Self {
+        let entry = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        Self {
+            label: entry >> 12,
+            exp: (entry >> 9) as u8 & 0x7,
+            s: entry & 0x100 != 0,
+            ttl: entry as u8,
+        }
+    }
+}
+
+/// The role of the interface described by an [`InterfaceInfo`] (RFC 5837).
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum InterfaceRole {
+    /// The interface the original datagram arrived on.
+    Incoming,
+    /// The sub-IP component of the interface the datagram arrived on.
+    IncomingSubIp,
+    /// The interface the datagram would have been forwarded on.
+    Outgoing,
+    /// The interface of the next hop the datagram would have been forwarded to.
+    NextHop,
+}
+
+/// Information about an interface of the hop which sent an error (RFC 5837).
+/// Every field other than the role is optional.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InterfaceInfo<'a> {
+    /// The role of the interface.
+    pub role: InterfaceRole,
+    /// The ifIndex of the interface.
+    pub if_index: Option<u32>,
+    /// An IP address of the interface.
+    pub addr: Option<IpAddr>,
+    /// The name of the interface.
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    pub name: Option<&'a str>,
+    /// The MTU of the interface.
+    pub mtu: Option<u32>,
+}
+
+impl<'a> InterfaceInfo<'a> {
+    fn parse(c_type: u8, mut data: &'a [u8]) -> Option<Self> {
+        fn take<'b>(data: &mut &'b [u8], len: usize) -> Option<&'b [u8]> {
+            if data.len() < len {
+                return None;
+            }
+            let (ret, rest) = data.split_at(len);
+            *data = rest;
+            Some(ret)
+        }
+        fn take_u32(data: &mut &[u8]) -> Option<u32> {
+            let bytes = take(data, 4)?;
+            Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        }
+        let role = match c_type >> 6 {
+            0 => InterfaceRole::Incoming,
+            1 => InterfaceRole::IncomingSubIp,
+            2 => InterfaceRole::Outgoing,
+            _ => InterfaceRole::NextHop,
+        };
+        let if_index = if c_type & 0x08 != 0 {
+            Some(take_u32(&mut data)?)
+        } else {
+            None
+        };
+        let addr = if c_type & 0x04 != 0 {
+            let afi = take(&mut data, 4)?;
+            match u16::from_be_bytes([afi[0], afi[1]]) {
+                1 => {
+                    let a = take(&mut data, 4)?;
+                    Some(IpAddr::V4(Ipv4Addr::new(a[0], a[1], a[2], a[3])))
+                }
+                2 => {
+                    let mut a = [0; 16];
+                    a.copy_from_slice(take(&mut data, 16)?);
+                    Some(IpAddr::V6(Ipv6Addr::from(a)))
+                }
+                _ => return None,
+            }
+        } else {
+            None
+        };
+        let name = if c_type & 0x02 != 0 {
+            // The length includes the length byte and padding, and is a multiple of 4
+            let len = *data.first()? as usize;
+            if len == 0 || len % 4 != 0 {
+                return None;
+            }
+            let name = &take(&mut data, len)?[1..];
+            let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+            Some(std::str::from_utf8(name).ok()?)
+        } else {
+            None
+        };
+        let mtu = if c_type & 0x01 != 0 {
+            Some(take_u32(&mut data)?)
+        } else {
+            None
+        };
+        Some(Self {
+            role,
+            if_index,
+            addr,
+            name,
+            mtu,
+        })
+    }
+}
+
+/// The start of the original datagram, quoted in an ICMP error message. This is the datagram
+/// as it looked at the hop which sent the error, so differences from what was sent (eg a
+/// remarked TOS) show what happened to it along the path.
+///
+/// This is a standalone parser. A [`Buffer`](crate::Buffer) has no quoted datagram for an
+/// error, as IcmpSendEcho only reports the error's status and sender, and matches it to
+/// the request itself. The bytes must come from elsewhere, eg a packet capture or a raw socket
+/// ([`TimestampPinger`](crate::TimestampPinger) matches errors to its requests this way).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct QuotedDatagram<'a> {
+    ip: IpHeader,
+    echo: Option<EchoHeader>,
+    payload: &'a [u8],
+}
+
+impl<'a> QuotedDatagram<'a> {
+    /// Parses a quoted datagram, starting at its IP header. The IP version is taken from the
+    /// header. Returns None if the IP header is incomplete.
+    pub fn parse(bytes: &'a [u8]) -> Option<Self> {
+        let (ip, rest) = match bytes.first()? >> 4 {
+            4 => {
+                let (header, rest) = Ipv4Header::parse(bytes)?;
+                (IpHeader::V4(header), rest)
+            }
+            6 => {
+                let (header, rest) = Ipv6Header::parse(bytes)?;
+                (IpHeader::V6(header), rest)
+            }
+            _ => return None,
+        };
+        let echo_proto = match ip {
+            IpHeader::V4(_) => PROTO_ICMP,
+            IpHeader::V6(_) => PROTO_ICMPV6,
+        };
+        let (echo, payload) = match EchoHeader::parse(rest) {
+            Some(echo) if ip.protocol() == echo_proto => (Some(echo), &rest[8..]),
+            _ => (None, rest),
+        };
+        Some(Self { ip, echo, payload })
+    }
+    /// Gets the quoted IP header.
+    pub fn ip(&self) -> &IpHeader {
+        &self.ip
+    }
+    /// Gets the quoted ICMP echo header, if the quoted datagram is ICMP.
+    pub fn echo(&self) -> Option<&EchoHeader> {
+        self.echo.as_ref()
+    }
+    /// Gets whatever is quoted after the headers. This is usually a truncated copy of the
+    /// request data.
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+    /// Checks whether this is a quote of an echo request sent to `dst` with `request_data`,
+    /// ie whether the error belongs to that request. Since routers may truncate the quote,
+    /// the quoted payload only needs to be a prefix of the request data.
+    pub fn matches(&self, dst: IpAddr, request_data: &[u8]) -> bool {
+        let is_request = match self.echo {
+            Some(echo) => echo.icmp_type == ECHO_REQUEST || echo.icmp_type == ECHO_REQUEST_V6,
+            None => false,
+        };
+        is_request && self.ip.dst() == dst && request_data.starts_with(self.payload)
+    }
+}
+
+/// A quoted IPv4 or IPv6 header.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IpHeader {
+    V4(Ipv4Header),
+    V6(Ipv6Header),
+}
+
+impl IpHeader {
+    /// Gets the source address.
+    pub fn src(&self) -> IpAddr {
+        match self {
+            IpHeader::V4(header) => IpAddr::V4(header.src),
+            IpHeader::V6(header) => IpAddr::V6(header.src),
+        }
+    }
+    /// Gets the destination address.
+    pub fn dst(&self) -> IpAddr {
+        match self {
+            IpHeader::V4(header) => IpAddr::V4(header.dst),
+            IpHeader::V6(header) => IpAddr::V6(header.dst),
+        }
+    }
+    /// Gets the IPv4 TTL or IPv6 hop limit.
+    pub fn ttl(&self) -> u8 {
+        match self {
+            IpHeader::V4(header) => header.ttl,
+            IpHeader::V6(header) => header.hop_limit,
+        }
+    }
+    /// Gets the IPv4 protocol or IPv6 next header.
+    pub fn protocol(&self) -> u8 {
+        match self {
+            IpHeader::V4(header) => header.protocol,
+            IpHeader::V6(header) => header.next_header,
+        }
+    }
+}
+
+/// A quoted IPv4 header. Options are skipped.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ipv4Header {
+    /// The type of service (DSCP and ECN) byte.
+    pub tos: u8,
+    /// The total length of the original datagram.
+    pub total_len: u16,
+    /// The identification field.
+    pub id: u16,
+    /// Whether the Don't Fragment bit is set.
+    pub df: bool,
+    /// The TTL when the datagram reached the reporting hop.
+    pub ttl: u8,
+    /// The protocol of the original datagram.
+    pub protocol: u8,
+    /// The source address.
+    pub src: Ipv4Addr,
+    /// The destination address.
+    pub dst: Ipv4Addr,
+}
+
+impl Ipv4Header {
+    fn parse(bytes: &[u8]) -> Option<(Self, &[u8])> {
+        let header_len = (*bytes.first()? as usize & 0xf) * 4;
+        if header_len < 20 || bytes.len() < header_len {
+            return None;
+        }
+        let header = Self {
+            tos: bytes[1],
+            total_len: u16::from_be_bytes([bytes[2], bytes[3]]),
+            id: u16::from_be_bytes([bytes[4], bytes[5]]),
+            df: bytes[6] & 0x40 != 0,
+            ttl: bytes[8],
+            protocol: bytes[9],
+            src: Ipv4Addr::new(bytes[12], bytes[13], bytes[14], bytes[15]),
+            dst: Ipv4Addr::new(bytes[16], bytes[17], bytes[18], bytes[19]),
+        };
+        Some((header, &bytes[header_len..]))
+    }
+}
+
+/// A quoted IPv6 header. Extension headers are not parsed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ipv6Header {
+    /// The traffic class (DSCP and ECN) byte.
+    pub traffic_class: u8,
+    /// The 20 bit flow label.
+    pub flow_label: u32,
+    /// The payload length of the original datagram.
+    pub payload_len: u16,
+    /// The next header of the original datagram.
+    pub next_header: u8,
+    /// The hop limit when the datagram reached the reporting hop.
+    pub hop_limit: u8,
+    /// The source address.
+    pub src: Ipv6Addr,
+    /// The destination address.
+    pub dst: Ipv6Addr,
+}
+
+impl Ipv6Header {
+    fn parse(bytes: &[u8]) -> Option<(Self, &[u8])> {
+        let bytes_40 = bytes.get(..40)?;
+        let first = u32::from_be_bytes([bytes_40[0], bytes_40[1], bytes_40[2], bytes_40[3]]);
+        let mut src = [0; 16];
+        let mut dst = [0; 16];
+        src.copy_from_slice(&bytes_40[8..24]);
+        dst.copy_from_slice(&bytes_40[24..40]);
+        let header = Self {
+            traffic_class: (first >> 20) as u8,
+            flow_label: first & 0xf_ffff,
+            payload_len: u16::from_be_bytes([bytes_40[4], bytes_40[5]]),
+            next_header: bytes_40[6],
+            hop_limit: bytes_40[7],
+            src: Ipv6Addr::from(src),
+            dst: Ipv6Addr::from(dst),
+        };
+        Some((header, &bytes[40..]))
+    }
+}
+
+/// A quoted ICMP or ICMPv6 echo header.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EchoHeader {
+    /// The ICMP type, 8 for an ICMPv4 echo request and 128 for ICMPv6.
+    pub icmp_type: u8,
+    /// The ICMP code.
+    pub code: u8,
+    /// The ICMP checksum.
+    pub checksum: u16,
+    /// The echo identifier.
+    pub identifier: u16,
+    /// The echo sequence number.
+    pub sequence: u16,
+}
+
+impl EchoHeader {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let bytes = bytes.get(..8)?;
+        Some(Self {
+            icmp_type: bytes[0],
+            code: bytes[1],
+            checksum: u16::from_be_bytes([bytes[2], bytes[3]]),
+            identifier: u16::from_be_bytes([bytes[4], bytes[5]]),
+            sequence: u16::from_be_bytes([bytes[6], bytes[7]]),
+        })
+    }
+}
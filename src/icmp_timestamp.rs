@@ -0,0 +1,242 @@
+use std::{
+    convert::TryInto,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// ICMP Timestamp (type 13) and Timestamp Reply (type 14) messages, RFC 792.
+//
+// The Windows ICMP API (IcmpSendEcho and friends) only sends echo requests, so these are
+// sent by TimestampPinger on a raw socket, which needs elevated rights. The codec is public
+// for callers which send and receive the messages some other way.
+
+const TIMESTAMP: u8 = 13;
+const TIMESTAMP_REPLY: u8 = 14;
+const MESSAGE_LEN: usize = 20;
+const DAY_MS: u32 = 24 * 60 * 60 * 1000;
+// Set in a timestamp which isn't milliseconds since midnight UT
+const NON_STANDARD: u32 = 1 << 31;
+
+/// An ICMP Timestamp or Timestamp Reply message (RFC 792).
+///
+/// Timestamps are milliseconds since midnight UT. A host which can't provide that may
+/// set the high bit and use any time, see [`is_standard`](Self::is_standard).
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IcmpTimestamp {
+    /// The identifier, which matches replies to requests.
+    pub id: u16,
+    /// The sequence number, which matches replies to requests.
+    pub seq: u16,
+    /// The time the request was sent, by the requester's clock.
+    pub originate: u32,
+    /// The time the request was received, by the replier's clock. 0 in a request.
+    pub receive: u32,
+    /// The time the reply was sent, by the replier's clock. 0 in a request.
+    pub transmit: u32,
+}
+
+/// Delay estimates from a Timestamp Reply, as returned by [`IcmpTimestamp::delays`].
+/// All times are in milliseconds, and the one way delays include the clock offset
+/// between the hosts, so they may be negative.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimestampDelays {
+    /// The round trip time, excluding the time the replier took to reply.
+    pub rtt: i32,
+    /// The delay from the requester to the replier, `receive - originate`.
+    pub forward: i32,
+    /// The delay from the replier to the requester, `received - transmit`.
+    pub backward: i32,
+    /// The estimated offset of the replier's clock from the requester's, assuming the
+    /// path is symmetric. Positive if the replier's clock is ahead.
+    pub offset: i32,
+    /// The time the replier took to reply, `transmit - receive`.
+    pub processing: i32,
+}
+
+/// A Timestamp Reply, as returned by [`TimestampPinger::send`](crate::TimestampPinger::send).
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimestampReply {
+    /// The reply, with the originate, receive and transmit timestamps.
+    pub timestamp: IcmpTimestamp,
+    /// The time the reply was received, as milliseconds since midnight UT.
+    pub received: u32,
+    /// The round trip time in milliseconds, measured locally.
+    pub rtt: u32,
+    /// The delays estimated from the timestamps, or None if the replier's timestamps
+    /// are non-standard.
+    pub delays: Option<TimestampDelays>,
+}
+
+impl IcmpTimestamp {
+    /// Creates a Timestamp request, with the originate timestamp set to now.
+    pub fn request(id: u16, seq: u16) -> Self {
+        Self {
+            id,
+            seq,
+            originate: Self::now(),
+            receive: 0,
+            transmit: 0,
+        }
+    }
+    /// Gets the current time as milliseconds since midnight UT.
+    pub fn now() -> u32 {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        (since_epoch.as_millis() % DAY_MS as u128) as u32
+    }
+    /// Encodes this as a Timestamp request, starting at the ICMP header, with the checksum set.
+    pub fn encode_request(&self) -> [u8; MESSAGE_LEN] {
+        self.encode(TIMESTAMP)
+    }
+    /// Encodes this as a Timestamp Reply, starting at the ICMP header, with the checksum set.
+    pub fn encode_reply(&self) -> [u8; MESSAGE_LEN] {
+        self.encode(TIMESTAMP_REPLY)
+    }
+    fn encode(&self, icmp_type: u8) -> [u8; MESSAGE_LEN] {
+        let mut bytes = [0; MESSAGE_LEN];
+        bytes[0] = icmp_type;
+        bytes[4..6].copy_from_slice(&self.id.to_be_bytes());
+        bytes[6..8].copy_from_slice(&self.seq.to_be_bytes());
+        bytes[8..12].copy_from_slice(&self.originate.to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.receive.to_be_bytes());
+        bytes[16..20].copy_from_slice(&self.transmit.to_be_bytes());
+        let checksum = checksum(&bytes);
+        bytes[2..4].copy_from_slice(&checksum.to_be_bytes());
+        bytes
+    }
+    /// Parses a Timestamp request, starting at the ICMP header (not the IP header).
+    /// Returns None if the message is not a Timestamp request, is too short, or has
+    /// a bad checksum.
+    pub fn parse_request(bytes: &[u8]) -> Option<Self> {
+        Self::parse(bytes, TIMESTAMP)
+    }
+    /// Parses a Timestamp Reply, starting at the ICMP header (not the IP header).
+    /// Returns None if the message is not a Timestamp Reply, is too short, or has
+    /// a bad checksum.
+    pub fn parse_reply(bytes: &[u8]) -> Option<Self> {
+        Self::parse(bytes, TIMESTAMP_REPLY)
+    }
+    fn parse(bytes: &[u8], icmp_type: u8) -> Option<Self> {
+        let bytes = bytes.get(..MESSAGE_LEN)?;
+        if bytes[0] != icmp_type || bytes[1] != 0 || checksum(bytes) != 0 {
+            return None;
+        }
+        let u16_at = |at: usize| u16::from_be_bytes(bytes[at..at + 2].try_into().unwrap());
+        let u32_at = |at: usize| u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap());
+        Some(Self {
+            id: u16_at(4),
+            seq: u16_at(6),
+            originate: u32_at(8),
+            receive: u32_at(12),
+            transmit: u32_at(16),
+        })
+    }
+    /// Checks whether every timestamp is milliseconds since midnight UT, rather than a
+    /// non-standard time with the high bit set.
+    pub fn is_standard(&self) -> bool {
+        [self.originate, self.receive, self.transmit]
+            .iter()
+            .all(|&time| time & NON_STANDARD == 0 && time < DAY_MS)
+    }
+    /// Estimates the delays from this reply, received at the given time (see [`now`](Self::now)).
+    /// Times are compared modulo a day, so a reply spanning midnight works. Returns None if any
+    /// timestamp is non-standard, as they can't be compared.
+    pub fn delays(&self, received: u32) -> Option<TimestampDelays> {
+        if !self.is_standard() || received >= DAY_MS {
+            return None;
+        }
+        let forward = day_diff(self.receive, self.originate);
+        let backward = day_diff(received, self.transmit);
+        let processing = day_diff(self.transmit, self.receive);
+        let rtt = day_diff(received, self.originate) - processing;
+        Some(TimestampDelays {
+            rtt,
+            forward,
+            backward,
+            offset: (forward - backward) / 2,
+            processing,
+        })
+    }
+}
+
+// Gets a - b in milliseconds, as the shortest way around a day.
+fn day_diff(a: u32, b: u32) -> i32 {
+    let diff = (a as i64 - b as i64).rem_euclid(DAY_MS as i64);
+    if diff > DAY_MS as i64 / 2 {
+        (diff - DAY_MS as i64) as i32
+    } else {
+        diff as i32
+    }
+}
+
+// The Internet checksum (RFC 1071). Computing it over a message with a valid checksum gives 0.
+fn checksum(bytes: &[u8]) -> u16 {
+    let mut sum = bytes
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32)
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[test]
+fn timestamp_round_trip() {
+    let request = IcmpTimestamp {
+        id: 0x1234,
+        seq: 7,
+        originate: 1000,
+        receive: 0,
+        transmit: 0,
+    };
+    let bytes = request.encode_request();
+    assert_eq!(bytes[0], TIMESTAMP);
+    assert_eq!(IcmpTimestamp::parse_request(&bytes), Some(request));
+    assert_eq!(IcmpTimestamp::parse_reply(&bytes), None);
+
+    let mut corrupt = bytes;
+    corrupt[9] ^= 1;
+    assert_eq!(IcmpTimestamp::parse_request(&corrupt), None);
+}
+
+#[test]
+fn timestamp_delays() {
+    // 10 ms each way, 2 ms to reply, with the replier's clock 100 ms ahead
+    let reply = IcmpTimestamp {
+        id: 1,
+        seq: 1,
+        originate: 1000,
+        receive: 1110,
+        transmit: 1112,
+    };
+    let delays = reply.delays(1022).unwrap();
+    assert_eq!(
+        delays,
+        TimestampDelays {
+            rtt: 20,
+            forward: 110,
+            backward: -90,
+            offset: 100,
+            processing: 2,
+        }
+    );
+
+    // Across midnight
+    let reply = IcmpTimestamp {
+        originate: DAY_MS - 5,
+        receive: 5,
+        transmit: 5,
+        ..reply
+    };
+    assert_eq!(reply.delays(15).unwrap().rtt, 20);
+
+    let reply = IcmpTimestamp {
+        receive: NON_STANDARD | 5,
+        ..reply
+    };
+    assert_eq!(reply.delays(15), None);
+}
@@ -33,14 +33,47 @@
 mod async_pinger;
 mod buffer;
 mod error;
+mod icmp_error;
+mod icmp_timestamp;
+mod payload;
 mod pinger;
+mod pool;
+mod probe;
+mod rate;
+mod retry;
+mod rtt;
+mod socket;
+mod tcp_pinger;
+mod timestamp_pinger;
+mod udp_prober;
 pub(crate) mod util;
 
 #[cfg(feature = "async")]
-pub use async_pinger::{set_async_buffer_size, AsyncPinger, AsyncResult, PingFuture};
-pub use buffer::Buffer;
-pub use error::Error;
-pub use pinger::{CreateError, IpPair, Pinger};
+pub use async_pinger::{
+    set_async_buffer_size, AsyncPinger, AsyncResult, Dispatcher, PingBatch, PingFuture,
+};
+pub use buffer::{reply_area_size, Buffer, Replies, Reply, ReplyView, Responder};
+pub use error::{Error, Localized};
+pub use icmp_error::{
+    EchoHeader, IcmpErrorMessage, IcmpExtension, InterfaceInfo, InterfaceRole, IpHeader,
+    Ipv4Header, Ipv6Header, MplsLabel, QuotedDatagram,
+};
+pub use icmp_timestamp::{IcmpTimestamp, TimestampDelays, TimestampReply};
+pub use payload::{PayloadTiming, TimestampPayload};
+pub use pinger::{CreateError, Families, HandleHealth, Health, IpPair, Pinger};
+pub use pool::{BufferPool, PoolStats};
+#[cfg(feature = "async")]
+pub use probe::{AsyncProbe, ProbeFuture};
+pub use probe::{Probe, ProbeOptions, ProbeResult};
+pub use rate::{Rate, RateLimit, SubnetRate};
+pub use retry::RetryPolicy;
+pub use rtt::{AdaptiveTimeout, RttEstimate};
+#[cfg(feature = "async")]
+pub use tcp_pinger::TcpPingFuture;
+pub use tcp_pinger::TcpPinger;
+pub use timestamp_pinger::TimestampPinger;
+pub use udp_prober::UdpProber;
+pub use util::parse_ipv6_scoped;
 
 #[cfg(test)]
 mod tests;
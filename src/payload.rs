@@ -0,0 +1,138 @@
+use std::{
+    collections::hash_map::RandomState,
+    convert::TryInto,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU32, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::Buffer;
+
+// Identifies a payload written by TimestampPayload
+const MAGIC: [u8; 4] = *b"WPTS";
+// Magic, session ID, sequence number and send time
+const HEADER_LEN: usize = 20;
+
+/// A request data format which embeds a send timestamp, a sequence number and a session ID.
+///
+/// Echo replies carry the request data back unchanged, so the timestamp gives a round trip
+/// time measured by this process, independently of the one Windows reports. The difference
+/// between the two is the time spent locally, eg in a retry or an [`AsyncPinger`](crate::AsyncPinger)'s
+/// worker thread. The sequence number shows whether a reply is to an earlier request.
+///
+/// Timestamps are nanoseconds since the `TimestampPayload` was created, from a monotonic clock,
+/// so they can only be read by the same `TimestampPayload`.
+#[derive(Debug)]
+pub struct TimestampPayload {
+    session: u32,
+    epoch: Instant,
+    next_seq: AtomicU32,
+    len: usize,
+}
+
+/// The timing of a reply, as returned by [`TimestampPayload::read`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub struct PayloadTiming {
+    /// The sequence number of the request which was replied to.
+    pub seq: u32,
+    /// The round trip time measured from the timestamp in the reply.
+    pub rtt: Duration,
+    /// The round trip time reported by Windows, in milliseconds, if the reply was successful.
+    pub os_rtt: Option<u32>,
+    /// The time spent locally, which is how much longer `rtt` is than `os_rtt`. As `os_rtt`
+    /// is in whole milliseconds, this is only accurate to a millisecond.
+    pub overhead: Option<Duration>,
+    /// True if the reply is to an earlier request than the one last written to the buffer.
+    pub stale: bool,
+}
+
+impl Default for TimestampPayload {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TimestampPayload {
+    /// Creates a payload format with a random session ID.
+    pub fn new() -> Self {
+        let session = RandomState::new().build_hasher().finish() as u32;
+        Self::with_session(session)
+    }
+    /// Creates a payload format with the given session ID, which tells apart replies
+    /// to requests from different sessions, eg different processes.
+    pub fn with_session(session: u32) -> Self {
+        Self {
+            session,
+            epoch: Instant::now(),
+            next_seq: AtomicU32::new(0),
+            len: HEADER_LEN,
+        }
+    }
+    /// Sets the length of the request data, which is padded with zeros after the
+    /// timestamp. Lengths less than 20 bytes, the length of the timestamp, are treated as 20.
+    pub fn set_data_len(&mut self, len: usize) {
+        self.len = len.max(HEADER_LEN);
+    }
+    /// Gets the length of the request data.
+    pub fn data_len(&self) -> usize {
+        self.len
+    }
+    /// Gets the session ID.
+    pub fn session(&self) -> u32 {
+        self.session
+    }
+    /// Writes the request data for the next request into the buffer, and returns its
+    /// sequence number. This should be done just before sending, as the timestamp is
+    /// the time it's written.
+    pub fn write(&self, buf: &mut Buffer) -> u32 {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let sent = self.epoch.elapsed().as_nanos() as u64;
+        let data = &mut buf.request_data;
+        data.clear();
+        data.extend_from_slice(&MAGIC);
+        data.extend_from_slice(&self.session.to_be_bytes());
+        data.extend_from_slice(&seq.to_be_bytes());
+        data.extend_from_slice(&sent.to_be_bytes());
+        data.resize(self.len, 0);
+        seq
+    }
+    /// Reads the timing of the reply in the buffer, as received now. Returns None if
+    /// there's no reply, or the reply data wasn't written by this `TimestampPayload`.
+    pub fn read(&self, buf: &Buffer) -> Option<PayloadTiming> {
+        self.read_at(buf, Instant::now())
+    }
+    /// Reads the timing of the reply in the buffer, as received at the given time.
+    pub fn read_at(&self, buf: &Buffer, received: Instant) -> Option<PayloadTiming> {
+        let reply = buf.replies().next()?;
+        let (seq, sent) = self.decode(reply.data())?;
+        let rtt = received
+            .saturating_duration_since(self.epoch)
+            .saturating_sub(sent);
+        let os_rtt = reply.result().ok();
+        let overhead =
+            os_rtt.map(|os_rtt| rtt.saturating_sub(Duration::from_millis(os_rtt as u64)));
+        let stale = match self.decode(&buf.request_data) {
+            Some((request_seq, _)) => seq != request_seq,
+            None => false,
+        };
+        Some(PayloadTiming {
+            seq,
+            rtt,
+            os_rtt,
+            overhead,
+            stale,
+        })
+    }
+    // Gets the sequence number and send time from data written by this session.
+    fn decode(&self, data: &[u8]) -> Option<(u32, Duration)> {
+        if data.len() < HEADER_LEN || data[..4] != MAGIC {
+            return None;
+        }
+        let field = |at: usize| u32::from_be_bytes(data[at..at + 4].try_into().unwrap());
+        if field(4) != self.session {
+            return None;
+        }
+        let sent = u64::from_be_bytes(data[12..20].try_into().unwrap());
+        Some((field(8), Duration::from_nanos(sent)))
+    }
+}
@@ -1,11 +1,12 @@
 use winapi::{
     shared::{
         minwindef::TRUE,
-        ntdef::{HANDLE, NULL},
-        ws2def::AF_INET6,
+        ntdef::{HANDLE, NULL, VOID},
+        winerror::{ERROR_DEV_NOT_EXIST, ERROR_INVALID_HANDLE, ERROR_NETNAME_DELETED},
         ws2ipdef::SOCKADDR_IN6,
     },
     um::{
+        errhandlingapi::GetLastError,
         handleapi::INVALID_HANDLE_VALUE,
         icmpapi::{
             Icmp6CreateFile, Icmp6SendEcho2, IcmpCloseHandle, IcmpCreateFile, IcmpSendEcho,
@@ -20,26 +21,102 @@ use winapi::um::ipexport::IP_OPTION_INFORMATION;
 #[cfg(target_pointer_width = "64")]
 use winapi::um::ipexport::IP_OPTION_INFORMATION32 as IP_OPTION_INFORMATION;
 
+use static_assertions::assert_impl_all;
+
 use std::{
     fmt::{self, Debug, Display, Formatter},
-    net::{IpAddr, Ipv4Addr, Ipv6Addr},
-    sync::Arc,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddrV6},
+    sync::{
+        atomic::{AtomicPtr, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Instant,
 };
 
 use crate::{
-    util::{windows_ipv4, windows_ipv6},
-    Buffer, Error,
+    buffer::ReplyArea,
+    rate::Limiter,
+    rtt::Estimator,
+    util::{windows_ipv4, windows_sockaddr6},
+    AdaptiveTimeout, Buffer, Error, RateLimit, ReplyView, Responder, RetryPolicy, RttEstimate,
 };
 
+// Pinger is Send and Sync, and clones share their Handles. This is sound because:
+// - IcmpSendEcho2 and Icmp6SendEcho2 may be called on one handle from several threads at
+//   once. Each send has its own request and reply buffers, so they share only the handle.
+// - A reopen only swaps the handle pointer. The old handle is moved to `retired` rather than
+//   closed, so a send which loaded it before the swap still uses an open handle.
+// - Handles are only closed when the Slot drops, after the last Pinger clone is gone, so
+//   no send can be in progress. A reopen which loses a race closes its own new handle,
+//   which no other thread has seen.
 struct Handles {
-    v4: HANDLE,
-    v6: HANDLE,
+    v4: Slot,
+    v6: Slot,
+}
+// A handle which is INVALID_HANDLE_VALUE until successfully opened. A broken handle
+// is replaced, but not closed until drop, as other threads may still be using it.
+struct Slot {
+    handle: AtomicPtr<VOID>,
+    create: unsafe extern "system" fn() -> HANDLE,
+    error: fn(u32) -> Error,
+    opens: AtomicU32,
+    reopens: AtomicU32,
+    // NO_ERROR (0) if there hasn't been an error
+    last_error: AtomicU32,
+    retired: Mutex<Vec<OwnedHandle>>,
+}
+struct OwnedHandle(HANDLE);
+// Safety: ICMP handles can be used and closed from any thread
+unsafe impl Send for OwnedHandle {}
+/// The IP versions a [`Pinger`] can currently send on, as returned by
+/// [`Pinger::available_families`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub struct Families {
+    /// Whether the ICMPv4 handle is open.
+    pub v4: bool,
+    /// Whether the ICMPv6 handle is open.
+    pub v6: bool,
+}
+/// The health of a [`Pinger`]'s ICMP handles, as returned by [`Pinger::health`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub struct Health {
+    /// The health of the ICMPv4 handle.
+    pub v4: HandleHealth,
+    /// The health of the ICMPv6 handle.
+    pub v6: HandleHealth,
+}
+/// The health of one of a [`Pinger`]'s ICMP handles.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub struct HandleHealth {
+    /// Whether the handle is currently open.
+    pub open: bool,
+    /// The number of times the handle was opened, including reopens.
+    pub opens: u32,
+    /// The number of times the handle was reopened after it stopped working.
+    pub reopens: u32,
+    /// The last error from opening the handle, or which caused it to be reopened.
+    pub last_error: Option<Error>,
 }
 /// A pair of IP (v4 or v6) addresses, source and destination.
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum IpPair {
-    V4 { src: Ipv4Addr, dst: Ipv4Addr },
-    V6 { src: Ipv6Addr, dst: Ipv6Addr },
+    V4 {
+        src: Ipv4Addr,
+        dst: Ipv4Addr,
+    },
+    V6 {
+        src: Ipv6Addr,
+        dst: Ipv6Addr,
+    },
+    /// An IPv6 pair with scope IDs and flow info, for link-local addresses.
+    /// The ports are ignored.
+    V6Scoped {
+        src: SocketAddrV6,
+        dst: SocketAddrV6,
+    },
 }
 /// A pinger that blocks when sending.
 #[derive(Clone)]
@@ -48,28 +125,58 @@ pub struct Pinger {
     ttl: u8,
     df: bool,
     timeout: u32,
+    retry: Option<Arc<RetryPolicy>>,
+    rtt: Option<Arc<Estimator>>,
+    rate: Option<Arc<Limiter>>,
 }
-/// An error when creating a Pinger.
+assert_impl_all!(Pinger: Send, Sync);
+/// An error when creating a Pinger, with the error from the OS for each IP version
+/// which failed. A failed version can be retried later with [`Pinger::open_v4`] or
+/// [`Pinger::open_v6`].
 pub enum CreateError {
     /// The ICMPv4 handle could not be created.
-    NoV4(Pinger),
+    NoV4(Pinger, Error),
     /// The ICMPv6 handle could not be created.
-    NoV6(Pinger),
-    /// Neither handle could be created.
-    None,
+    NoV6(Pinger, Error),
+    /// Neither handle could be created. Contains the v4 error, then the v6 error.
+    None(Error, Error),
+}
+
+impl CreateError {
+    /// Gets the error from creating the ICMPv4 handle, if it failed.
+    pub fn v4_error(&self) -> Option<Error> {
+        match *self {
+            CreateError::NoV4(_, err) | CreateError::None(err, _) => Some(err),
+            CreateError::NoV6(..) => None,
+        }
+    }
+    /// Gets the error from creating the ICMPv6 handle, if it failed.
+    pub fn v6_error(&self) -> Option<Error> {
+        match *self {
+            CreateError::NoV6(_, err) | CreateError::None(_, err) => Some(err),
+            CreateError::NoV4(..) => None,
+        }
+    }
+    /// Gets the Pinger embedded in this error, if one of the handles was created.
+    pub fn into_pinger(self) -> Option<Pinger> {
+        match self {
+            CreateError::NoV4(pinger, _) | CreateError::NoV6(pinger, _) => Some(pinger),
+            CreateError::None(..) => None,
+        }
+    }
 }
 
 impl Debug for CreateError {
     fn fmt(&self, out: &mut Formatter) -> fmt::Result {
-        write!(
-            out,
-            "{}",
-            match self {
-                CreateError::None => "Failed to create ICMP V4 and V6 handles",
-                CreateError::NoV4(_) => "Failed to create ICMP V4 handle",
-                CreateError::NoV6(_) => "Failed to create ICMP V6 handle",
-            }
-        )
+        match self {
+            CreateError::None(v4, v6) => write!(
+                out,
+                "Failed to create ICMP V4 and V6 handles: {} / {}",
+                v4, v6
+            ),
+            CreateError::NoV4(_, err) => write!(out, "Failed to create ICMP V4 handle: {}", err),
+            CreateError::NoV6(_, err) => write!(out, "Failed to create ICMP V6 handle: {}", err),
+        }
     }
 }
 
@@ -81,34 +188,79 @@ impl Display for CreateError {
 
 impl std::error::Error for CreateError {}
 
+/// Converts to an io::Error with the same message. Any Pinger in the error is dropped.
+impl From<CreateError> for io::Error {
+    fn from(err: CreateError) -> Self {
+        io::Error::new(io::ErrorKind::Other, err.to_string())
+    }
+}
+
 impl Pinger {
     /// Creates a new Pinger.
     /// If one ICMP handle (v4 or v6) fails to initialize,
     /// this will return the Pinger embedded in an error. The
-    /// Pinger is still usable in this state, but sending
-    /// on the failed version will return an Error.
+    /// Pinger is still usable in this state, and sending
+    /// on the failed version will try to open it again.
     /// If both v4 and v6 fail, the pinger is not embedded
     /// in the error.
     pub fn new() -> Result<Self, CreateError> {
-        let (v4, v6) = unsafe { (IcmpCreateFile(), Icmp6CreateFile()) };
-        let ret = Self {
-            handles: Arc::new(Handles { v4, v6 }),
+        let ret = Self::new_lazy();
+        match (ret.open_v4(), ret.open_v6()) {
+            (Err(v4), Err(v6)) => Err(CreateError::None(v4, v6)),
+            (Ok(()), Err(err)) => Err(CreateError::NoV6(ret, err)),
+            (Err(err), Ok(())) => Err(CreateError::NoV4(ret, err)),
+            (Ok(()), Ok(())) => Ok(ret),
+        }
+    }
+    /// Creates a new Pinger without opening any ICMP handles. Each handle is opened
+    /// the first time it is needed, so this never fails. Errors opening a handle are
+    /// returned from the send.
+    pub fn new_lazy() -> Self {
+        Self {
+            handles: Arc::new(Handles {
+                v4: Slot::new(IcmpCreateFile, Error::from_winerror),
+                v6: Slot::new(Icmp6CreateFile, Error::from_winerror6),
+            }),
             ttl: 255,
             df: false,
             timeout: 2000,
-        };
-        match (v4, v6) {
-            (INVALID_HANDLE_VALUE, INVALID_HANDLE_VALUE) => Err(CreateError::None),
-            (INVALID_HANDLE_VALUE, _) => Err(CreateError::NoV6(ret)),
-            (_, INVALID_HANDLE_VALUE) => Err(CreateError::NoV4(ret)),
-            (_, _) => Ok(ret),
+            retry: None,
+            rtt: None,
+            rate: None,
+        }
+    }
+    /// Opens the ICMPv4 handle if it isn't already open, eg if it failed when the
+    /// Pinger was created. This affects every clone of this Pinger.
+    pub fn open_v4(&self) -> Result<(), Error> {
+        self.handles.v4.open()
+    }
+    /// Opens the ICMPv6 handle if it isn't already open, eg if IPv6 was not available
+    /// when the Pinger was created. This affects every clone of this Pinger.
+    pub fn open_v6(&self) -> Result<(), Error> {
+        self.handles.v6.open()
+    }
+    /// Gets the IP versions this Pinger can currently send on.
+    pub fn available_families(&self) -> Families {
+        Families {
+            v4: self.handles.v4.is_open(),
+            v6: self.handles.v6.is_open(),
+        }
+    }
+    /// Gets the health of this Pinger's ICMP handles, which are shared by every clone.
+    ///
+    /// Handles are opened when needed, and if a send fails because the handle stopped
+    /// working (eg the IP stack was restarted), it is reopened and the send is tried once more.
+    pub fn health(&self) -> Health {
+        Health {
+            v4: self.handles.v4.health(),
+            v6: self.handles.v6.health(),
         }
     }
     /// Creates a new Pinger, ignoring v6 failures. If you want to use
     /// both v4 and v6, use new() instead.
     pub fn new_v4() -> Option<Self> {
         match Self::new() {
-            Ok(ret) | Err(CreateError::NoV6(ret)) => Some(ret),
+            Ok(ret) | Err(CreateError::NoV6(ret, _)) => Some(ret),
             _ => None,
         }
     }
@@ -116,7 +268,7 @@ impl Pinger {
     /// both v4 and v6, use new() instead.
     pub fn new_v6() -> Option<Self> {
         match Self::new() {
-            Ok(ret) | Err(CreateError::NoV4(ret)) => Some(ret),
+            Ok(ret) | Err(CreateError::NoV4(ret, _)) => Some(ret),
             _ => None,
         }
     }
@@ -136,7 +288,8 @@ impl Pinger {
     pub fn df(&self) -> bool {
         self.df
     }
-    /// Sets the timeout, in milliseconds, for future requests.
+    /// Sets the timeout, in milliseconds, for future requests. This is not used while
+    /// there is an adaptive timeout.
     pub fn set_timeout(&mut self, timeout: u32) {
         self.timeout = timeout;
     }
@@ -144,6 +297,81 @@ impl Pinger {
     pub fn timeout(&self) -> u32 {
         self.timeout
     }
+    /// Sets the policy for retrying failed requests, or None to never retry. Defaults to None.
+    /// The number of attempts taken is available from [`Buffer::attempts`].
+    pub fn set_retry_policy(&mut self, policy: Option<RetryPolicy>) {
+        self.retry = policy.map(Arc::new);
+    }
+    /// Gets the current retry policy.
+    pub fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.retry.as_deref()
+    }
+    /// Sets an adaptive timeout, which derives the timeout for each request from previous
+    /// round trip times to the same destination, or None to use the fixed timeout.
+    /// Defaults to None. Setting this discards any previous round trip times.
+    pub fn set_adaptive_timeout(&mut self, adaptive: Option<AdaptiveTimeout>) {
+        self.rtt = adaptive.map(|settings| Arc::new(Estimator::new(settings)));
+    }
+    /// Gets the current adaptive timeout settings.
+    pub fn adaptive_timeout(&self) -> Option<&AdaptiveTimeout> {
+        self.rtt.as_deref().map(Estimator::settings)
+    }
+    /// Gets the round trip time estimate for a destination, if there is an adaptive timeout
+    /// and there has been a reply or timeout from the destination.
+    pub fn rtt_estimate(&self, dst: IpAddr) -> Option<RttEstimate> {
+        self.rtt.as_ref()?.estimate(dst)
+    }
+    /// Sets limits on how fast requests are sent, or None for no limits. Defaults to None.
+    /// Sends sleep until the limits allow the request.
+    pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.rate = limit.map(|limit| Arc::new(Limiter::new(limit)));
+    }
+    /// Gets the current rate limits.
+    pub fn rate_limit(&self) -> Option<&RateLimit> {
+        self.rate.as_deref().map(Limiter::limit)
+    }
+    // Makes attempts until one succeeds or the retry policy gives up, sleeping in between.
+    // Each attempt is given the timeout to use.
+    fn with_retry(
+        &self,
+        dst: IpAddr,
+        buf: &mut Buffer,
+        mut attempt: impl FnMut(&mut Buffer, u32) -> Result<u32, Error>,
+    ) -> Result<u32, Error> {
+        self.retry_loop(dst, |attempts, timeout| {
+            buf.set_attempts(attempts);
+            attempt(buf, timeout)
+        })
+    }
+    // Like with_retry, but each attempt is given its number, rather than a buffer.
+    fn retry_loop(
+        &self,
+        dst: IpAddr,
+        mut attempt: impl FnMut(u32, u32) -> Result<u32, Error>,
+    ) -> Result<u32, Error> {
+        let mut attempts = 1;
+        loop {
+            if let Some(rate) = &self.rate {
+                let now = Instant::now();
+                thread::sleep(rate.reserve(dst, now) - now);
+            }
+            let timeout = self
+                .rtt
+                .as_ref()
+                .map_or(self.timeout, |rtt| rtt.timeout(dst));
+            let res = attempt(attempts, timeout);
+            if let Some(rtt) = &self.rtt {
+                rtt.observe(dst, res);
+            }
+            match (res, &self.retry) {
+                (Err(err), Some(policy)) if policy.should_retry(err, attempts) => {
+                    thread::sleep(policy.delay(attempts));
+                    attempts += 1;
+                }
+                (res, _) => return res,
+            }
+        }
+    }
     #[inline]
     fn make_ip_opts(&self) -> IP_OPTION_INFORMATION {
         IP_OPTION_INFORMATION {
@@ -154,143 +382,151 @@ impl Pinger {
     }
     /// Send an ICMPv4 request to the destination address. On success, returns the round trip time in milliseconds.
     pub fn send4(&self, dst: Ipv4Addr, buf: &mut Buffer) -> Result<u32, Error> {
+        self.with_retry(dst.into(), buf, |buf, timeout| {
+            self.try_send4(dst, buf, timeout)
+        })
+    }
+    fn try_send4(&self, dst: Ipv4Addr, buf: &mut Buffer, timeout: u32) -> Result<u32, Error> {
         buf.init_for_send();
-        let ret = unsafe {
-            IcmpSendEcho(
-                self.handles.v4,
-                windows_ipv4(dst),
-                buf.request_data_ptr(),
-                buf.request_data_len(),
-                &mut self.make_ip_opts(),
-                buf.reply_data_ptr(),
-                buf.reply_data_len(),
-                self.timeout,
-            )
-        };
-        if ret == 0 {
-            Err(Error::from_lasterror())
+        let ret = self
+            .handles
+            .v4
+            .send(|handle| unsafe {
+                IcmpSendEcho(
+                    handle,
+                    windows_ipv4(dst),
+                    buf.request_data_ptr(),
+                    buf.request_data_len(),
+                    &mut self.make_ip_opts(),
+                    buf.reply_data_ptr(),
+                    buf.reply_data_len(),
+                    timeout,
+                )
+            })
+            .map_err(Error::from_winerror)?;
+        let reply = buf.as_echo_reply().unwrap();
+        let (status, rtt) = (reply.Status, reply.RoundTripTime);
+        buf.set_filled4(ret);
+        if status == IP_SUCCESS {
+            Ok(rtt)
         } else {
-            let reply = buf.as_echo_reply().unwrap();
-            let (status, rtt) = (reply.Status, reply.RoundTripTime);
-            buf.set_filled4();
-            if status == IP_SUCCESS {
-                Ok(rtt)
-            } else {
-                Err(Error::from_iperror(status))
-            }
+            Err(Error::from_iperror(status))
         }
     }
     /// Sends an ICMPv4 request from the source address to the destination address. On success, returns the round trip time in milliseconds.
     pub fn send4_from(&self, src: Ipv4Addr, dst: Ipv4Addr, buf: &mut Buffer) -> Result<u32, Error> {
+        self.with_retry(dst.into(), buf, |buf, timeout| {
+            self.try_send4_from(src, dst, buf, timeout)
+        })
+    }
+    fn try_send4_from(
+        &self,
+        src: Ipv4Addr,
+        dst: Ipv4Addr,
+        buf: &mut Buffer,
+        timeout: u32,
+    ) -> Result<u32, Error> {
         buf.init_for_send();
-        let ret = unsafe {
-            IcmpSendEcho2Ex(
-                self.handles.v4,
-                NULL,      // Event
-                NULL as _, // ApcRoutine
-                NULL,      // ApcContext
-                windows_ipv4(src),
-                windows_ipv4(dst),
-                buf.request_data_ptr(),
-                buf.request_data_len(),
-                &mut self.make_ip_opts(),
-                buf.reply_data_ptr(),
-                buf.reply_data_len(),
-                self.timeout,
-            )
-        };
-        if ret == 0 {
-            Err(Error::from_lasterror())
+        let ret = self
+            .handles
+            .v4
+            .send(|handle| unsafe {
+                IcmpSendEcho2Ex(
+                    handle,
+                    NULL,      // Event
+                    NULL as _, // ApcRoutine
+                    NULL,      // ApcContext
+                    windows_ipv4(src),
+                    windows_ipv4(dst),
+                    buf.request_data_ptr(),
+                    buf.request_data_len(),
+                    &mut self.make_ip_opts(),
+                    buf.reply_data_ptr(),
+                    buf.reply_data_len(),
+                    timeout,
+                )
+            })
+            .map_err(Error::from_winerror)?;
+        let reply = buf.as_echo_reply().unwrap();
+        let (status, rtt) = (reply.Status, reply.RoundTripTime);
+        buf.set_filled4(ret);
+        if status == IP_SUCCESS {
+            Ok(rtt)
         } else {
-            let reply = buf.as_echo_reply().unwrap();
-            let (status, rtt) = (reply.Status, reply.RoundTripTime);
-            buf.set_filled4();
-            if status == IP_SUCCESS {
-                Ok(rtt)
-            } else {
-                Err(Error::from_iperror(status))
-            }
+            Err(Error::from_iperror(status))
         }
     }
     /// Sends an ICMPv6 request to the destination address. On success, returns the round trip time in milliseconds.
     pub fn send6(&self, dst: Ipv6Addr, buf: &mut Buffer) -> Result<u32, Error> {
-        let mut dst = SOCKADDR_IN6 {
-            sin6_family: AF_INET6 as _,
-            sin6_addr: windows_ipv6(dst),
-            ..Default::default()
-        };
-        buf.init_for_send();
-
-        let ret = unsafe {
-            Icmp6SendEcho2(
-                self.handles.v6,
-                NULL,      // Event
-                NULL as _, // ApcRoutine
-                NULL,      // ApcContext
-                &mut SOCKADDR_IN6::default(),
-                &mut dst,
-                buf.request_data_ptr(),
-                buf.request_data_len(),
-                &mut self.make_ip_opts(),
-                buf.reply_data_ptr(),
-                buf.reply_data_len(),
-                self.timeout,
-            )
-        };
-        if ret == 0 {
-            Err(Error::from_lasterror())
-        } else {
-            let reply = buf.as_echo_reply6().unwrap();
-            let (status, rtt) = (reply.Status, reply.RoundTripTime as u32);
-            buf.set_filled6();
-            if status == IP_SUCCESS {
-                Ok(rtt)
-            } else {
-                Err(Error::from_iperror(status))
-            }
-        }
+        self.send6_scoped(SocketAddrV6::new(dst, 0, 0, 0), buf)
     }
     /// Sends an ICMPv6 request from the source address to the destination address. On success, returns the round trip time in milliseconds.
     pub fn send6_from(&self, src: Ipv6Addr, dst: Ipv6Addr, buf: &mut Buffer) -> Result<u32, Error> {
-        let mut dst = SOCKADDR_IN6 {
-            sin6_family: AF_INET6 as _,
-            sin6_addr: windows_ipv6(dst),
-            ..Default::default()
-        };
-        let mut src = SOCKADDR_IN6 {
-            sin6_family: AF_INET6 as _,
-            sin6_addr: windows_ipv6(src),
-            ..Default::default()
-        };
+        self.send6_scoped_from(
+            SocketAddrV6::new(src, 0, 0, 0),
+            SocketAddrV6::new(dst, 0, 0, 0),
+            buf,
+        )
+    }
+    /// Sends an ICMPv6 request to the destination address, using its scope ID and flow info.
+    /// This is needed to reach link-local addresses on hosts with more than one interface.
+    /// The port is ignored. On success, returns the round trip time in milliseconds.
+    pub fn send6_scoped(&self, dst: SocketAddrV6, buf: &mut Buffer) -> Result<u32, Error> {
+        let ip = IpAddr::V6(*dst.ip());
+        let (mut src, mut dst) = (SOCKADDR_IN6::default(), windows_sockaddr6(dst));
+        self.with_retry(ip, buf, |buf, timeout| {
+            self.send6_sockaddr(&mut src, &mut dst, buf, timeout)
+        })
+    }
+    /// Sends an ICMPv6 request from the source address to the destination address, using their
+    /// scope IDs and flow info. The ports are ignored. On success, returns the round trip time in milliseconds.
+    pub fn send6_scoped_from(
+        &self,
+        src: SocketAddrV6,
+        dst: SocketAddrV6,
+        buf: &mut Buffer,
+    ) -> Result<u32, Error> {
+        let ip = IpAddr::V6(*dst.ip());
+        let (mut src, mut dst) = (windows_sockaddr6(src), windows_sockaddr6(dst));
+        self.with_retry(ip, buf, |buf, timeout| {
+            self.send6_sockaddr(&mut src, &mut dst, buf, timeout)
+        })
+    }
+    fn send6_sockaddr(
+        &self,
+        src: &mut SOCKADDR_IN6,
+        dst: &mut SOCKADDR_IN6,
+        buf: &mut Buffer,
+        timeout: u32,
+    ) -> Result<u32, Error> {
         buf.init_for_send();
 
-        let ret = unsafe {
-            Icmp6SendEcho2(
-                self.handles.v6,
-                NULL,      // Event
-                NULL as _, // ApcRoutine
-                NULL,      // ApcContext
-                &mut src,
-                &mut dst,
-                buf.request_data_ptr(),
-                buf.request_data_len(),
-                &mut self.make_ip_opts(),
-                buf.reply_data_ptr(),
-                buf.reply_data_len(),
-                self.timeout,
-            )
-        };
-        if ret == 0 {
-            Err(Error::from_lasterror())
+        self.handles
+            .v6
+            .send(|handle| unsafe {
+                Icmp6SendEcho2(
+                    handle,
+                    NULL,      // Event
+                    NULL as _, // ApcRoutine
+                    NULL,      // ApcContext
+                    &mut *src,
+                    &mut *dst,
+                    buf.request_data_ptr(),
+                    buf.request_data_len(),
+                    &mut self.make_ip_opts(),
+                    buf.reply_data_ptr(),
+                    buf.reply_data_len(),
+                    timeout,
+                )
+            })
+            .map_err(Error::from_winerror6)?;
+        let reply = buf.as_echo_reply6().unwrap();
+        let (status, rtt) = (reply.Status, reply.RoundTripTime as u32);
+        buf.set_filled6();
+        if status == IP_SUCCESS {
+            Ok(rtt)
         } else {
-            let reply = buf.as_echo_reply6().unwrap();
-            let (status, rtt) = (reply.Status, reply.RoundTripTime as u32);
-            buf.set_filled6();
-            if status == IP_SUCCESS {
-                Ok(rtt)
-            } else {
-                Err(Error::from_iperror(status))
-            }
+            Err(Error::from_iperror6(status))
         }
     }
     /// Sends an ICMP request to the destination address. Supports both v4 and v6. On success, returns the round trip time in milliseconds.
@@ -300,24 +536,228 @@ impl Pinger {
             IpAddr::V6(ip) => self.send6(ip, buf),
         }
     }
+    /// Sends an ICMP request with the payload to the destination address, and writes the reply
+    /// into reply_area rather than a [`Buffer`], so nothing is copied or allocated. Supports both v4 and v6.
+    ///
+    /// reply_area needs at least [`reply_area_size`](crate::reply_area_size) bytes. It doesn't need
+    /// to be aligned, as up to 7 bytes at its start are skipped to align it. Returns a view of the
+    /// reply which borrows reply_area, whose result is [`Error::BufferTooSmall`] if reply_area is
+    /// too small or the payload is longer than 65535 bytes.
+    pub fn send_borrowed<'a>(
+        &self,
+        dst: IpAddr,
+        payload: &[u8],
+        reply_area: &'a mut [u8],
+    ) -> ReplyView<'a> {
+        let mut area = ReplyArea::new(reply_area);
+        if payload.len() > u16::MAX as usize || !area.fits(payload.len()) {
+            return area.into_view(Err(Error::BufferTooSmall), 0);
+        }
+        let mut attempts = 0;
+        let res = self.retry_loop(dst, |attempt, timeout| {
+            attempts = attempt;
+            self.try_send_borrowed(dst, payload, &mut area, timeout)
+        });
+        area.into_view(res, attempts)
+    }
+    fn try_send_borrowed(
+        &self,
+        dst: IpAddr,
+        payload: &[u8],
+        area: &mut ReplyArea,
+        timeout: u32,
+    ) -> Result<u32, Error> {
+        // The request data isn't written to, despite the pointer being mut
+        let data_ptr = payload.as_ptr() as *mut VOID;
+        let data_len = payload.len() as u16;
+        let (status, rtt) = match dst {
+            IpAddr::V4(dst) => {
+                let ret = self
+                    .handles
+                    .v4
+                    .send(|handle| unsafe {
+                        IcmpSendEcho(
+                            handle,
+                            windows_ipv4(dst),
+                            data_ptr,
+                            data_len,
+                            &mut self.make_ip_opts(),
+                            area.ptr(),
+                            area.len(),
+                            timeout,
+                        )
+                    })
+                    .map_err(Error::from_winerror)?;
+                area.set_filled4(ret)
+            }
+            IpAddr::V6(dst) => {
+                let mut src = SOCKADDR_IN6::default();
+                let mut dst = windows_sockaddr6(SocketAddrV6::new(dst, 0, 0, 0));
+                self.handles
+                    .v6
+                    .send(|handle| unsafe {
+                        Icmp6SendEcho2(
+                            handle,
+                            NULL,      // Event
+                            NULL as _, // ApcRoutine
+                            NULL,      // ApcContext
+                            &mut src,
+                            &mut dst,
+                            data_ptr,
+                            data_len,
+                            &mut self.make_ip_opts(),
+                            area.ptr(),
+                            area.len(),
+                            timeout,
+                        )
+                    })
+                    .map_err(Error::from_winerror6)?;
+                // RFC 4443, section 4.2, reply data MUST be same as request data
+                area.set_filled6(payload.len())
+            }
+        };
+        match (status, dst) {
+            (IP_SUCCESS, _) => Ok(rtt),
+            (status, IpAddr::V4(_)) => Err(Error::from_iperror(status)),
+            (status, IpAddr::V6(_)) => Err(Error::from_iperror6(status)),
+        }
+    }
+    /// Sends an ICMP request to a broadcast or multicast destination address, and collects every
+    /// host which replied before the timeout. At most [`Buffer::max_replies`] replies are collected,
+    /// so set that first with [`Buffer::set_max_replies`]. Returns an error if no host replied successfully.
+    ///
+    /// Windows only ever returns one reply to an ICMPv6 request (`Icmp6ParseReplies` parses a
+    /// single reply), so the list has at most one entry for IPv6. A link-local multicast address
+    /// such as `ff02::1` also needs a scope, see [`send6_scoped_collect`](Self::send6_scoped_collect).
+    pub fn send_collect(&self, dst: IpAddr, buf: &mut Buffer) -> Result<Vec<Responder>, Error> {
+        let res = self.send(dst, buf);
+        collect(res, buf)
+    }
+    /// Sends an ICMPv6 request to a multicast destination address with a scope ID, eg
+    /// `ff02::1%12`, and collects the host which replied, like [`send_collect`](Self::send_collect).
+    /// As Windows only returns one reply to an ICMPv6 request, the list has at most one entry.
+    pub fn send6_scoped_collect(
+        &self,
+        dst: SocketAddrV6,
+        buf: &mut Buffer,
+    ) -> Result<Vec<Responder>, Error> {
+        let res = self.send6_scoped(dst, buf);
+        collect(res, buf)
+    }
     /// Sends an ICMP request from the source address to the destination address. Supports both v4 and v6. On success, returns the round trip time in milliseconds.
     pub fn send_from(&mut self, src_dst_pair: IpPair, buf: &mut Buffer) -> Result<u32, Error> {
         match src_dst_pair {
             IpPair::V4 { src, dst } => self.send4_from(src, dst, buf),
             IpPair::V6 { src, dst } => self.send6_from(src, dst, buf),
+            IpPair::V6Scoped { src, dst } => self.send6_scoped_from(src, dst, buf),
         }
     }
 }
 
-impl Drop for Handles {
-    fn drop(&mut self) {
-        if self.v4 != INVALID_HANDLE_VALUE {
-            let ret = unsafe { IcmpCloseHandle(self.v4) };
-            debug_assert_eq!(TRUE, ret);
+// Gets the hosts which replied successfully, or the error if none did.
+fn collect(res: Result<u32, Error>, buf: &Buffer) -> Result<Vec<Responder>, Error> {
+    let responders = buf.responders();
+    match res {
+        Err(err) if responders.is_empty() => Err(err),
+        _ => Ok(responders),
+    }
+}
+
+impl Slot {
+    fn new(create: unsafe extern "system" fn() -> HANDLE, error: fn(u32) -> Error) -> Self {
+        Self {
+            handle: AtomicPtr::new(INVALID_HANDLE_VALUE),
+            create,
+            error,
+            opens: AtomicU32::new(0),
+            reopens: AtomicU32::new(0),
+            last_error: AtomicU32::new(0),
+            retired: Mutex::new(Vec::new()),
+        }
+    }
+    fn is_open(&self) -> bool {
+        self.handle.load(Ordering::Acquire) != INVALID_HANDLE_VALUE
+    }
+    fn open(&self) -> Result<(), Error> {
+        self.get().map(drop).map_err(self.error)
+    }
+    fn health(&self) -> HandleHealth {
+        HandleHealth {
+            open: self.is_open(),
+            opens: self.opens.load(Ordering::Relaxed),
+            reopens: self.reopens.load(Ordering::Relaxed),
+            last_error: match self.last_error.load(Ordering::Relaxed) {
+                0 => None,
+                err => Some((self.error)(err)),
+            },
+        }
+    }
+    // Gets the handle, opening it if needed. Errors are Windows error codes.
+    fn get(&self) -> Result<HANDLE, u32> {
+        match self.handle.load(Ordering::Acquire) {
+            INVALID_HANDLE_VALUE => self.replace(INVALID_HANDLE_VALUE),
+            handle => Ok(handle),
         }
-        if self.v6 != INVALID_HANDLE_VALUE {
-            let ret = unsafe { IcmpCloseHandle(self.v6) };
-            debug_assert_eq!(TRUE, ret);
+    }
+    // Opens a new handle in place of old, unless another thread already replaced it.
+    fn replace(&self, old: HANDLE) -> Result<HANDLE, u32> {
+        let new = unsafe { (self.create)() };
+        if new == INVALID_HANDLE_VALUE {
+            let err = unsafe { GetLastError() };
+            self.last_error.store(err, Ordering::Relaxed);
+            return Err(err);
+        }
+        match self
+            .handle
+            .compare_exchange(old, new, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => {
+                self.opens.fetch_add(1, Ordering::Relaxed);
+                if old != INVALID_HANDLE_VALUE {
+                    self.reopens.fetch_add(1, Ordering::Relaxed);
+                    self.retired.lock().unwrap().push(OwnedHandle(old));
+                }
+                Ok(new)
+            }
+            Err(current) => {
+                drop(OwnedHandle(new));
+                Ok(current)
+            }
         }
     }
+    // Calls send with the handle, which returns 0 on failure like the IcmpSendEcho
+    // functions. If the handle is broken, it is reopened and send is called once more.
+    fn send(&self, mut send: impl FnMut(HANDLE) -> u32) -> Result<u32, u32> {
+        let handle = self.get()?;
+        let err = match send(handle) {
+            0 => unsafe { GetLastError() },
+            ret => return Ok(ret),
+        };
+        match err {
+            ERROR_INVALID_HANDLE | ERROR_DEV_NOT_EXIST | ERROR_NETNAME_DELETED => {
+                self.last_error.store(err, Ordering::Relaxed);
+                match send(self.replace(handle)?) {
+                    0 => Err(unsafe { GetLastError() }),
+                    ret => Ok(ret),
+                }
+            }
+            _ => Err(err),
+        }
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let handle = *self.handle.get_mut();
+        if handle != INVALID_HANDLE_VALUE {
+            drop(OwnedHandle(handle));
+        }
+    }
+}
+
+impl Drop for OwnedHandle {
+    fn drop(&mut self) {
+        let ret = unsafe { IcmpCloseHandle(self.0) };
+        debug_assert_eq!(TRUE, ret);
+    }
 }
@@ -0,0 +1,141 @@
+use std::sync::Mutex;
+
+use crate::Buffer;
+#[cfg(feature = "async")]
+use crate::{AsyncResult, Error};
+
+/// A pool of [`Buffer`]s for sending many requests without allocating.
+///
+/// Buffers are handed out with the pool's request data and room for their replies,
+/// and returned with [`put`](Self::put) once the reply has been read. Once there are
+/// enough buffers for the requests in flight, getting and putting buffers doesn't allocate.
+/// A pool can be shared between threads.
+#[derive(Debug)]
+pub struct BufferPool {
+    template: Vec<u8>,
+    max_replies: usize,
+    max_idle: usize,
+    inner: Mutex<Inner>,
+}
+
+/// Statistics for a [`BufferPool`], as returned by [`BufferPool::stats`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Hash)]
+pub struct PoolStats {
+    /// The number of buffers created because the pool was empty.
+    pub allocated: u64,
+    /// The number of buffers handed out which were reused.
+    pub reused: u64,
+    /// The number of buffers returned to the pool.
+    pub returned: u64,
+    /// The number of returned buffers which were dropped because the pool was full.
+    pub dropped: u64,
+    /// The number of buffers currently in the pool.
+    pub idle: usize,
+}
+
+#[derive(Debug)]
+struct Inner {
+    idle: Vec<Buffer>,
+    stats: PoolStats,
+}
+
+impl BufferPool {
+    /// Creates a pool of buffers with the given request data, which keeps up to
+    /// 1024 idle buffers.
+    pub fn new(request_data: Vec<u8>) -> Self {
+        Self::with_max_idle(request_data, 1024)
+    }
+    /// Creates a pool of buffers with the given request data, which keeps up to
+    /// max_idle idle buffers. Buffers returned to a full pool are dropped.
+    pub fn with_max_idle(request_data: Vec<u8>, max_idle: usize) -> Self {
+        Self {
+            template: request_data,
+            max_replies: 1,
+            max_idle,
+            inner: Mutex::new(Inner {
+                idle: Vec::new(),
+                stats: PoolStats::default(),
+            }),
+        }
+    }
+    /// Sets the maximum number of replies the pool's buffers have room for, see
+    /// [`Buffer::set_max_replies`]. Buffers already in the pool are resized when
+    /// they're handed out.
+    pub fn set_max_replies(&mut self, max_replies: usize) {
+        self.max_replies = max_replies.max(1);
+    }
+    /// Gets the maximum number of replies the pool's buffers have room for.
+    pub fn max_replies(&self) -> usize {
+        self.max_replies
+    }
+    /// Gets the request data of the pool's buffers.
+    pub fn request_data(&self) -> &[u8] {
+        &self.template
+    }
+    /// Creates buffers until the pool has count idle buffers, or is full, so that
+    /// later calls to [`get`](Self::get) don't allocate.
+    pub fn preallocate(&self, count: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        let Inner { idle, stats } = &mut *inner;
+        let count = count.min(self.max_idle);
+        idle.reserve(count.saturating_sub(idle.len()));
+        while idle.len() < count {
+            idle.push(self.create());
+            stats.allocated += 1;
+        }
+    }
+    /// Gets a buffer from the pool, or creates one if the pool is empty.
+    pub fn get(&self) -> Buffer {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.idle.pop() {
+            Some(mut buf) => {
+                inner.stats.reused += 1;
+                drop(inner);
+                buf.set_max_replies(self.max_replies);
+                buf.init_for_send();
+                buf
+            }
+            None => {
+                inner.stats.allocated += 1;
+                drop(inner);
+                self.create()
+            }
+        }
+    }
+    /// Returns a buffer to the pool. Its request data is reset to the pool's, and its
+    /// replies are discarded.
+    pub fn put(&self, mut buf: Buffer) {
+        if buf.request_data != self.template {
+            buf.request_data.clear();
+            buf.request_data.extend_from_slice(&self.template);
+        }
+        buf.recycle();
+        let mut inner = self.inner.lock().unwrap();
+        inner.stats.returned += 1;
+        if inner.idle.len() < self.max_idle {
+            inner.idle.push(buf);
+        } else {
+            inner.stats.dropped += 1;
+        }
+    }
+    /// Returns the buffer of an [`AsyncResult`] to the pool, and returns its result.
+    #[cfg(feature = "async")]
+    pub fn put_result(&self, res: AsyncResult) -> Result<u32, Error> {
+        self.put(res.buffer);
+        res.result
+    }
+    /// Gets the pool's statistics.
+    pub fn stats(&self) -> PoolStats {
+        let inner = self.inner.lock().unwrap();
+        PoolStats {
+            idle: inner.idle.len(),
+            ..inner.stats
+        }
+    }
+    fn create(&self) -> Buffer {
+        let mut buf = Buffer::with_data(self.template.clone());
+        buf.set_max_replies(self.max_replies);
+        buf.init_for_send();
+        buf
+    }
+}
@@ -0,0 +1,334 @@
+use std::net::{IpAddr, SocketAddr};
+#[cfg(feature = "async")]
+use std::{
+    future::{self, Future},
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+use crate::{socket::socket_addrs, Buffer, Error, IpPair, Pinger, TcpPinger, UdpProber};
+#[cfg(feature = "async")]
+use crate::{AsyncPinger, PingFuture};
+
+/// A way of probing whether a host can be reached, such as an ICMP echo, a TCP connection
+/// or a UDP datagram. This lets monitoring code pick the kind of probe per target, eg as a
+/// `Box<dyn Probe>`.
+///
+/// Implemented by [`Pinger`], [`AsyncPinger`] (which blocks until the request completes),
+/// [`TcpPinger`] and [`UdpProber`].
+pub trait Probe {
+    /// Probes the target, with the prober's own settings overridden by any set in options.
+    fn probe(&self, target: IpAddr, options: &ProbeOptions) -> ProbeResult;
+}
+
+/// The future returned by [`AsyncProbe::probe_async`].
+#[cfg(feature = "async")]
+pub type ProbeFuture = Pin<Box<dyn Future<Output = ProbeResult> + Send>>;
+
+/// The async version of [`Probe`].
+///
+/// Implemented by [`AsyncPinger`], [`TcpPinger`], [`Pinger`] and [`UdpProber`]. Only
+/// `AsyncPinger` shares a worker thread between requests; the others probe on a thread
+/// of their own, so they're meant for a modest number of probes at once.
+#[cfg(feature = "async")]
+pub trait AsyncProbe {
+    /// Probes the target, with the prober's own settings overridden by any set in options.
+    fn probe_async(&self, target: IpAddr, options: &ProbeOptions) -> ProbeFuture;
+}
+
+/// Settings for a single probe, which override the prober's own when set.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProbeOptions {
+    /// The IP TTL.
+    pub ttl: Option<u8>,
+    /// The timeout in milliseconds. This is not used by a pinger with an adaptive timeout.
+    pub timeout: Option<u32>,
+    /// The address to send from, which must be the same IP version as the target.
+    pub source: Option<IpAddr>,
+    /// The port, for probes which use one. ICMP probes ignore it.
+    pub port: Option<u16>,
+}
+
+/// The result of a probe, of any kind.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProbeResult {
+    /// Whether the target answered, or the error.
+    pub result: Result<(), Error>,
+    /// The round trip time in milliseconds. This is also set for an error which was a reply,
+    /// eg [`Error::TtlExpired`] from a router, so traceroute hops have timings. None if
+    /// nothing replied.
+    pub rtt: Option<u32>,
+    /// The address which responded, if known. For an ICMP probe, this is the sender of the
+    /// reply, eg the router which reported [`Error::TtlExpired`].
+    pub responder: Option<IpAddr>,
+}
+
+impl ProbeResult {
+    fn icmp(result: Result<u32, Error>, buf: &Buffer) -> Self {
+        Self {
+            result: result.map(|_| ()),
+            rtt: result
+                .ok()
+                .or_else(|| buf.replies().next().map(|reply| reply.rtt())),
+            responder: buf.responding_ip(),
+        }
+    }
+    // The target is the responder when it answered, or refused a connection or datagram,
+    // as only the target reports its own port as unreachable.
+    // Windows doesn't say who sent any other ICMP error for a socket.
+    fn socket(result: Result<u32, (Error, Option<u32>)>, target: IpAddr) -> Self {
+        let (result, rtt) = match result {
+            Ok(rtt) => (Ok(()), Some(rtt)),
+            Err((err, rtt)) => (Err(err), rtt),
+        };
+        let responder = match result {
+            Ok(()) | Err(Error::PortUnreachable) => Some(target),
+            Err(_) => None,
+        };
+        Self {
+            result,
+            rtt,
+            responder,
+        }
+    }
+    fn bad_source() -> Self {
+        Self {
+            result: Err(Error::BadRequest),
+            rtt: None,
+            responder: None,
+        }
+    }
+}
+
+impl ProbeOptions {
+    fn pair(&self, target: IpAddr) -> Result<Option<IpPair>, ProbeResult> {
+        Ok(match (self.source, target) {
+            (None, _) => None,
+            (Some(IpAddr::V4(src)), IpAddr::V4(dst)) => Some(IpPair::V4 { src, dst }),
+            (Some(IpAddr::V6(src)), IpAddr::V6(dst)) => Some(IpPair::V6 { src, dst }),
+            _ => return Err(ProbeResult::bad_source()),
+        })
+    }
+}
+
+// Clones a prober with the options applied.
+macro_rules! configured {
+    ($prober:expr, $options:expr) => {{
+        let mut prober = $prober.clone();
+        if let Some(ttl) = $options.ttl {
+            prober.set_ttl(ttl);
+        }
+        if let Some(timeout) = $options.timeout {
+            prober.set_timeout(timeout);
+        }
+        prober
+    }};
+    ($prober:expr, $options:expr, port) => {{
+        let mut prober = configured!($prober, $options);
+        if let Some(port) = $options.port {
+            prober.set_port(port);
+        }
+        prober
+    }};
+}
+
+impl Probe for Pinger {
+    fn probe(&self, target: IpAddr, options: &ProbeOptions) -> ProbeResult {
+        let pair = match options.pair(target) {
+            Ok(pair) => pair,
+            Err(res) => return res,
+        };
+        let mut pinger = configured!(self, options);
+        let mut buf = Buffer::new();
+        let result = match pair {
+            None => pinger.send(target, &mut buf),
+            Some(pair) => pinger.send_from(pair, &mut buf),
+        };
+        ProbeResult::icmp(result, &buf)
+    }
+}
+
+impl Probe for TcpPinger {
+    fn probe(&self, target: IpAddr, options: &ProbeOptions) -> ProbeResult {
+        let pair = match options.pair(target) {
+            Ok(pair) => pair,
+            Err(res) => return res,
+        };
+        let pinger = configured!(self, options, port);
+        let result = match pair {
+            None => pinger.send(target),
+            Some(pair) => pinger.send_from(pair),
+        };
+        // A refusal isn't timed, as Windows only reports it when the attempt gives up
+        ProbeResult::socket(result.map_err(|err| (err, None)), target)
+    }
+}
+
+impl Probe for UdpProber {
+    fn probe(&self, target: IpAddr, options: &ProbeOptions) -> ProbeResult {
+        let pair = match options.pair(target) {
+            Ok(pair) => pair,
+            Err(res) => return res,
+        };
+        let prober = configured!(self, options, port);
+        let (src, dst) = match pair {
+            None => (None, SocketAddr::new(target, prober.port())),
+            Some(pair) => {
+                let (src, dst) = socket_addrs(pair, prober.port());
+                (Some(src), dst)
+            }
+        };
+        ProbeResult::socket(prober.try_send(src, dst), target)
+    }
+}
+
+#[cfg(feature = "async")]
+impl Probe for AsyncPinger {
+    fn probe(&self, target: IpAddr, options: &ProbeOptions) -> ProbeResult {
+        match ping_async(self, target, options) {
+            Ok(future) => {
+                let res = future.wait();
+                ProbeResult::icmp(res.result, &res.buffer)
+            }
+            Err(res) => res,
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+fn ping_async(
+    pinger: &AsyncPinger,
+    target: IpAddr,
+    options: &ProbeOptions,
+) -> Result<PingFuture, ProbeResult> {
+    let pair = options.pair(target)?;
+    let pinger = configured!(pinger, options);
+    Ok(match pair {
+        None => pinger.send(target, Buffer::new()),
+        Some(pair) => pinger.send_from(pair, Buffer::new()),
+    })
+}
+
+#[cfg(feature = "async")]
+impl AsyncProbe for AsyncPinger {
+    fn probe_async(&self, target: IpAddr, options: &ProbeOptions) -> ProbeFuture {
+        match ping_async(self, target, options) {
+            Ok(future) => Box::pin(async move {
+                let res = future.await;
+                ProbeResult::icmp(res.result, &res.buffer)
+            }),
+            Err(res) => Box::pin(future::ready(res)),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl AsyncProbe for TcpPinger {
+    fn probe_async(&self, target: IpAddr, options: &ProbeOptions) -> ProbeFuture {
+        let pair = match options.pair(target) {
+            Ok(pair) => pair,
+            Err(res) => return Box::pin(future::ready(res)),
+        };
+        let pinger = configured!(self, options, port);
+        let future = match pair {
+            None => pinger.send_async(target),
+            Some(pair) => pinger.send_from_async(pair),
+        };
+        Box::pin(async move {
+            let result = future.await.map_err(|err| (err, None));
+            ProbeResult::socket(result, target)
+        })
+    }
+}
+
+#[cfg(feature = "async")]
+impl AsyncProbe for Pinger {
+    fn probe_async(&self, target: IpAddr, options: &ProbeOptions) -> ProbeFuture {
+        let (pinger, options) = (self.clone(), *options);
+        Box::pin(ThreadFuture::spawn(move || pinger.probe(target, &options)))
+    }
+}
+
+#[cfg(feature = "async")]
+impl AsyncProbe for UdpProber {
+    fn probe_async(&self, target: IpAddr, options: &ProbeOptions) -> ProbeFuture {
+        let (prober, options) = (self.clone(), *options);
+        Box::pin(ThreadFuture::spawn(move || prober.probe(target, &options)))
+    }
+}
+
+/// A future for the result of a function run on a thread of its own.
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub(crate) struct ThreadFuture<T> {
+    shared: Arc<ThreadShared<T>>,
+}
+
+#[cfg(feature = "async")]
+#[derive(Debug)]
+struct ThreadShared<T> {
+    state: Mutex<ThreadState<T>>,
+    done: Condvar,
+}
+
+#[cfg(feature = "async")]
+#[derive(Debug)]
+struct ThreadState<T> {
+    result: Option<T>,
+    waker: Option<Waker>,
+}
+
+#[cfg(feature = "async")]
+impl<T: Send + 'static> ThreadFuture<T> {
+    pub(crate) fn spawn(f: impl FnOnce() -> T + Send + 'static) -> Self {
+        let shared = Arc::new(ThreadShared {
+            state: Mutex::new(ThreadState {
+                result: None,
+                waker: None,
+            }),
+            done: Condvar::new(),
+        });
+        let thread_shared = shared.clone();
+        thread::spawn(move || {
+            let result = f();
+            let mut state = thread_shared.state.lock().unwrap();
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+            thread_shared.done.notify_all();
+        });
+        Self { shared }
+    }
+    pub(crate) fn is_ready(&self) -> bool {
+        self.shared.state.lock().unwrap().result.is_some()
+    }
+    pub(crate) fn wait(self) -> T {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(result) = state.result.take() {
+                return result;
+            }
+            state = self.shared.done.wait(state).unwrap();
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> Future for ThreadFuture<T> {
+    type Output = T;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
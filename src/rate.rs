@@ -0,0 +1,198 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// A token bucket rate: requests may be sent at `per_second` on average, with
+/// bursts of up to `burst` requests at once.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rate {
+    /// The average number of requests per second. A rate which isn't positive, including NaN,
+    /// means no limit, so set it with [`Rate::per_second`], which rejects one.
+    pub per_second: f64,
+    /// The number of requests which may be sent at once. Values less than 1 are treated as 1.
+    pub burst: u32,
+}
+
+impl Rate {
+    /// Creates a rate of per_second requests per second, with no bursts.
+    ///
+    /// # Panics
+    ///
+    /// Panics if per_second isn't positive, or is NaN, as that would turn the limit off.
+    pub fn per_second(per_second: f64) -> Self {
+        assert!(
+            per_second > 0.0,
+            "rate must be positive, not {}",
+            per_second
+        );
+        Self {
+            per_second,
+            burst: 1,
+        }
+    }
+    // Rates below one request per day are treated as one request per day, and rates
+    // which aren't positive as no limit.
+    fn interval(&self) -> Duration {
+        const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+        if self.per_second > 0.0 {
+            Duration::try_from_secs_f64(1.0 / self.per_second).map_or(DAY, |i| i.min(DAY))
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+/// Limits on how fast requests are sent, set with `set_rate_limit` on a
+/// [`Pinger`](crate::Pinger) or [`AsyncPinger`](crate::AsyncPinger).
+///
+/// Each request (including retries) waits until every limit allows it. A [`Pinger`](crate::Pinger)
+/// sleeps, while an [`AsyncPinger`](crate::AsyncPinger) holds the request in its worker thread,
+/// so the returned future takes longer to complete. Limits are shared by clones of a pinger.
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RateLimit {
+    /// A limit on all requests.
+    pub global: Option<Rate>,
+    /// A limit on the requests to each destination.
+    pub per_destination: Option<Rate>,
+    /// A limit on the requests to each subnet.
+    pub per_subnet: Option<SubnetRate>,
+}
+
+/// A limit on the requests to each subnet, for [`RateLimit::per_subnet`].
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SubnetRate {
+    /// The prefix length of an IPv4 subnet, eg 24.
+    pub v4_prefix: u8,
+    /// The prefix length of an IPv6 subnet, eg 64.
+    pub v6_prefix: u8,
+    /// The rate for each subnet.
+    pub rate: Rate,
+}
+
+// Each bucket is tracked with the generic cell rate algorithm. Rather than a token
+// count, it keeps the theoretical arrival time (TAT) of the next request. A request
+// may be sent once it's no more than a burst of intervals before the TAT.
+#[derive(Copy, Clone, Debug)]
+struct Bucket {
+    tat: Instant,
+}
+
+impl Bucket {
+    fn earliest(&self, rate: &Rate) -> Instant {
+        let tolerance = rate.interval() * (rate.burst.max(1) - 1);
+        self.tat.checked_sub(tolerance).unwrap_or(self.tat)
+    }
+    fn take(&mut self, rate: &Rate, at: Instant) {
+        self.tat = self.tat.max(at) + rate.interval();
+    }
+}
+
+// Key for the per destination and per subnet buckets.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+enum Key {
+    Destination(IpAddr),
+    Subnet(IpAddr),
+}
+
+// Tracks the buckets for a RateLimit. Shared by clones of a pinger.
+#[derive(Debug)]
+pub(crate) struct Limiter {
+    limit: RateLimit,
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    global: Bucket,
+    keyed: HashMap<Key, Bucket>,
+    // The number of keyed buckets at which idle ones are next removed
+    prune_len: usize,
+}
+
+// Idle buckets are first removed once there are this many. After that, once there are
+// twice as many as were still busy, so a pass happens at most once per doubling.
+const PRUNE_LEN: usize = 1024;
+
+impl Limiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: Mutex::new(Buckets {
+                global: Bucket {
+                    tat: Instant::now(),
+                },
+                keyed: HashMap::new(),
+                prune_len: PRUNE_LEN,
+            }),
+        }
+    }
+    pub(crate) fn limit(&self) -> &RateLimit {
+        &self.limit
+    }
+    /// Reserves a slot for a request to dst, no sooner than earliest, and returns
+    /// when the request may be sent.
+    pub(crate) fn reserve(&self, dst: IpAddr, earliest: Instant) -> Instant {
+        let limit = &self.limit;
+        let mut limits = Vec::with_capacity(3);
+        if let Some(rate) = limit.global {
+            limits.push((None, rate));
+        }
+        if let Some(rate) = limit.per_destination {
+            limits.push((Some(Key::Destination(dst)), rate));
+        }
+        if let Some(subnet) = limit.per_subnet {
+            let net = mask(dst, subnet.v4_prefix, subnet.v6_prefix);
+            limits.push((Some(Key::Subnet(net)), subnet.rate));
+        }
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.keyed.len() >= buckets.prune_len {
+            // A bucket whose TAT has passed is the same as a new one
+            let now = Instant::now();
+            buckets.keyed.retain(|_, bucket| bucket.tat > now);
+            buckets.prune_len = PRUNE_LEN.max(buckets.keyed.len() * 2);
+        }
+        let at = limits
+            .iter()
+            .map(|&(key, rate)| buckets.get(key, earliest).earliest(&rate))
+            .fold(earliest, Instant::max);
+        for (key, rate) in limits {
+            buckets.get(key, earliest).take(&rate, at);
+        }
+        at
+    }
+}
+
+impl Buckets {
+    fn get(&mut self, key: Option<Key>, now: Instant) -> &mut Bucket {
+        match key {
+            None => &mut self.global,
+            Some(key) => self.keyed.entry(key).or_insert(Bucket { tat: now }),
+        }
+    }
+}
+
+fn mask(addr: IpAddr, v4_prefix: u8, v6_prefix: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(ip) => {
+            let bits = u32::from(ip);
+            let mask = u32::MAX
+                .checked_shl(32 - v4_prefix.min(32) as u32)
+                .unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(bits & mask))
+        }
+        IpAddr::V6(ip) => {
+            let bits = u128::from(ip);
+            let mask = u128::MAX
+                .checked_shl(128 - v6_prefix.min(128) as u32)
+                .unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(bits & mask))
+        }
+    }
+}
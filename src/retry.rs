@@ -0,0 +1,83 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use crate::Error;
+
+/// A policy for retrying failed requests, set with `set_retry_policy` on a
+/// [`Pinger`](crate::Pinger) or [`AsyncPinger`](crate::AsyncPinger).
+///
+/// After a failed attempt, the request is retried if there are attempts left and the error
+/// is retryable. The number of attempts taken is available from [`Buffer::attempts`](crate::Buffer::attempts).
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RetryPolicy {
+    /// The maximum number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// The delay before the first retry. Each later retry waits twice as long as the
+    /// one before it, up to `max_backoff`.
+    pub backoff: Duration,
+    /// The longest delay between attempts.
+    pub max_backoff: Duration,
+    /// The amount of random jitter applied to each delay, as a fraction of the delay
+    /// from 0.0 (none) to 1.0 (anywhere from no delay to twice the delay).
+    pub jitter: f32,
+    /// The errors which are retried. If None, errors are retried if
+    /// [`Error::is_transient`] returns true.
+    pub retry_on: Option<Vec<Error>>,
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts, starting with a 100 ms backoff, up to 2 seconds, with 10% jitter,
+    /// retrying transient errors.
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            jitter: 0.1,
+            retry_on: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Creates a policy which makes up to max_attempts attempts, with the default backoff.
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            ..Default::default()
+        }
+    }
+    /// Checks whether a request which failed with err after the given number
+    /// of attempts should be retried.
+    pub fn should_retry(&self, err: Error, attempts: u32) -> bool {
+        if attempts >= self.max_attempts {
+            return false;
+        }
+        match &self.retry_on {
+            Some(errors) => errors.contains(&err),
+            None => err.is_transient(),
+        }
+    }
+    /// Gets the delay before the next attempt, after the given number of attempts.
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let exp = attempts.saturating_sub(1).min(31);
+        let delay = self
+            .backoff
+            .checked_mul(1 << exp)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff);
+        // A NaN jitter means no jitter
+        let jitter = match self.jitter {
+            jitter if jitter > 0.0 => jitter.min(1.0) as f64,
+            _ => return delay,
+        };
+        // A random number from -1.0 to 1.0, without depending on a random crate
+        let random = RandomState::new().build_hasher().finish();
+        let random = (random >> 11) as f64 / (1u64 << 52) as f64 - 1.0;
+        delay.mul_f64(1.0 + jitter * random)
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::Error;
+
+/// Settings for an adaptive timeout, set with `set_adaptive_timeout` on a
+/// [`Pinger`](crate::Pinger) or [`AsyncPinger`](crate::AsyncPinger).
+///
+/// With an adaptive timeout, the round trip times to each destination are tracked
+/// in the style of TCP's retransmission timer (RFC 6298), and the timeout for the next
+/// request to that destination is derived from them. All times are in milliseconds.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AdaptiveTimeout {
+    /// The timeout for a destination with no round trip times yet.
+    pub initial: u32,
+    /// The shortest timeout.
+    pub min: u32,
+    /// The longest timeout.
+    pub max: u32,
+}
+
+impl Default for AdaptiveTimeout {
+    /// Starts at 2 seconds, the default fixed timeout, and stays between 100 ms and 10 seconds.
+    fn default() -> Self {
+        Self {
+            initial: 2000,
+            min: 100,
+            max: 10_000,
+        }
+    }
+}
+
+/// The round trip time estimate for one destination, as returned by `rtt_estimate`.
+/// All times are in milliseconds.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RttEstimate {
+    /// The smoothed round trip time (SRTT).
+    pub srtt: f64,
+    /// The round trip time variation (RTTVAR).
+    pub rttvar: f64,
+    /// The timeout for the next request (RTO).
+    pub rto: u32,
+    /// The number of round trip times measured. If this is 0, there have only been
+    /// timeouts, and `srtt` and `rttvar` are 0.
+    pub samples: u32,
+}
+
+// Tracks an RttEstimate per destination. Shared by clones of a pinger.
+#[derive(Debug)]
+pub(crate) struct Estimator {
+    settings: AdaptiveTimeout,
+    estimates: Mutex<Estimates>,
+}
+
+#[derive(Debug)]
+struct Estimates {
+    entries: HashMap<IpAddr, Entry>,
+    // The length at which stale entries are next removed
+    prune_len: usize,
+}
+
+#[derive(Debug)]
+struct Entry {
+    estimate: RttEstimate,
+    observed: Instant,
+}
+
+// Once there are this many estimates, those not observed within STALE_MAXES times the
+// longest timeout are removed. A destination which is pinged again starts afresh. So that
+// many live destinations don't mean pruning on every request, the next prune waits until
+// there are twice as many estimates as were kept.
+const PRUNE_LEN: usize = 1024;
+const STALE_MAXES: u64 = 64;
+
+impl Estimator {
+    pub(crate) fn new(settings: AdaptiveTimeout) -> Self {
+        Self {
+            settings,
+            estimates: Mutex::new(Estimates {
+                entries: HashMap::new(),
+                prune_len: PRUNE_LEN,
+            }),
+        }
+    }
+    pub(crate) fn settings(&self) -> &AdaptiveTimeout {
+        &self.settings
+    }
+    pub(crate) fn estimate(&self, dst: IpAddr) -> Option<RttEstimate> {
+        self.estimates
+            .lock()
+            .unwrap()
+            .entries
+            .get(&dst)
+            .map(|entry| entry.estimate)
+    }
+    /// Gets the timeout for the next request to dst.
+    pub(crate) fn timeout(&self, dst: IpAddr) -> u32 {
+        self.estimate(dst)
+            .map_or(self.clamp(self.settings.initial as f64), |e| e.rto)
+    }
+    /// Updates the estimate for dst with the result of a request. Only round trip
+    /// times and timeouts are used, other errors say nothing about the path.
+    pub(crate) fn observe(&self, dst: IpAddr, result: Result<u32, Error>) {
+        let now = Instant::now();
+        let mut estimates = self.estimates.lock().unwrap();
+        let estimates = &mut *estimates;
+        if estimates.entries.len() >= estimates.prune_len {
+            let stale = Duration::from_millis(self.settings.max as u64 * STALE_MAXES);
+            let entries = &mut estimates.entries;
+            entries.retain(|_, entry| now.duration_since(entry.observed) < stale);
+            estimates.prune_len = PRUNE_LEN.max(entries.len() * 2);
+        }
+        let entries = &mut estimates.entries;
+        let estimate = match (result, entries.get(&dst).map(|entry| entry.estimate)) {
+            (Ok(rtt), Some(mut e)) if e.samples > 0 => {
+                // RFC 6298 section 2.3, with alpha = 1/8 and beta = 1/4
+                let rtt = rtt as f64;
+                e.rttvar = 0.75 * e.rttvar + 0.25 * (e.srtt - rtt).abs();
+                e.srtt = 0.875 * e.srtt + 0.125 * rtt;
+                e.rto = self.rto(e.srtt, e.rttvar);
+                e.samples += 1;
+                e
+            }
+            (Ok(rtt), _) => {
+                // RFC 6298 section 2.2
+                let (srtt, rttvar) = (rtt as f64, rtt as f64 / 2.0);
+                let rto = self.rto(srtt, rttvar);
+                let samples = 1;
+                RttEstimate {
+                    srtt,
+                    rttvar,
+                    rto,
+                    samples,
+                }
+            }
+            // RFC 6298 section 5.5, back off the timer
+            (Err(Error::Timeout), Some(e)) => RttEstimate {
+                rto: self.clamp(e.rto as f64 * 2.0),
+                ..e
+            },
+            (Err(Error::Timeout), None) => {
+                // An estimate with no samples, which only holds the backed off timeout
+                let rto = self.clamp(self.settings.initial as f64 * 2.0);
+                let (srtt, rttvar, samples) = (0.0, 0.0, 0);
+                RttEstimate {
+                    srtt,
+                    rttvar,
+                    rto,
+                    samples,
+                }
+            }
+            (Err(_), _) => return,
+        };
+        let observed = now;
+        entries.insert(dst, Entry { estimate, observed });
+    }
+    fn rto(&self, srtt: f64, rttvar: f64) -> u32 {
+        // Windows reports round trip times in whole milliseconds, so the clock
+        // granularity G is 1 ms
+        self.clamp(srtt + (4.0 * rttvar).max(1.0))
+    }
+    fn clamp(&self, rto: f64) -> u32 {
+        let min = self.settings.min as f64;
+        let max = (self.settings.max as f64).max(min);
+        rto.ceil().min(max).max(min) as u32
+    }
+}
@@ -0,0 +1,295 @@
+use winapi::{
+    shared::{
+        minwindef::{DWORD, MAKEWORD, TRUE},
+        winerror::{WSAECONNREFUSED, WSAECONNRESET, WSAENETRESET},
+        ws2def::{
+            AF_INET, AF_INET6, IPPROTO_IP, IPPROTO_IPV6, IPPROTO_TCP, SOCKADDR, SOCK_DGRAM,
+            SOCK_STREAM,
+        },
+        ws2ipdef::{IPV6_UNICAST_HOPS, IP_TTL},
+    },
+    um::{
+        mswsock::{SIO_UDP_CONNRESET, SIO_UDP_NETRESET},
+        winsock2::{
+            bind, closesocket, connect, fd_set, getsockopt, ioctlsocket, linger, recv, select,
+            send, sendto, setsockopt, socket, timeval, WSAGetLastError, WSAIoctl, WSAStartup,
+            FD_SETSIZE, FIONBIO, INVALID_SOCKET, SOCKET, SOCKET_ERROR, SOL_SOCKET, SO_ERROR,
+            SO_LINGER, WSADATA, WSAEMSGSIZE, WSAEWOULDBLOCK,
+        },
+    },
+};
+
+use std::{
+    mem,
+    net::{SocketAddr, SocketAddrV6},
+    os::raw::c_int,
+    ptr,
+    sync::Once,
+};
+
+use crate::{
+    util::{sockaddr_inet_len, windows_sockaddr_inet},
+    Error, IpPair,
+};
+
+// TCP socket options from ws2ipdef.h, which winapi lacks. TCP_MAXRTMS needs Windows 10 1607.
+const TCP_MAXRT: c_int = 5;
+const TCP_MAXRTMS: c_int = 14;
+
+/// A Winsock socket, which is closed on drop.
+pub(crate) struct Socket {
+    socket: SOCKET,
+    ty: c_int,
+    v6: bool,
+}
+
+impl Socket {
+    /// Creates a socket for the address family of addr.
+    pub(crate) fn new(addr: SocketAddr, ty: c_int, protocol: c_int) -> Result<Self, Error> {
+        startup();
+        let v6 = addr.is_ipv6();
+        let family = if v6 { AF_INET6 } else { AF_INET };
+        match unsafe { socket(family, ty, protocol) } {
+            INVALID_SOCKET => Err(Error::from_winerror(unsafe { WSAGetLastError() } as u32)),
+            socket => Ok(Self { socket, ty, v6 }),
+        }
+    }
+    /// Sets the TTL (IPv4) or hop limit (IPv6) of outgoing packets.
+    pub(crate) fn set_ttl(&self, ttl: u8) -> Result<(), Error> {
+        let (level, name) = if self.v6 {
+            (IPPROTO_IPV6 as c_int, IPV6_UNICAST_HOPS)
+        } else {
+            (IPPROTO_IP as c_int, IP_TTL)
+        };
+        self.set_option(level, name, ttl as DWORD)
+    }
+    /// Makes closing the socket send a reset, rather than leave it in TIME_WAIT.
+    pub(crate) fn set_reset_on_close(&self) -> Result<(), Error> {
+        let linger = linger {
+            l_onoff: 1,
+            l_linger: 0,
+        };
+        self.set_option(SOL_SOCKET, SO_LINGER, linger)
+    }
+    /// Makes a UDP socket report ICMP port unreachable errors as WSAECONNRESET, and
+    /// time exceeded errors as WSAENETRESET, from the next send or receive.
+    pub(crate) fn set_udp_error_reports(&self) -> Result<(), Error> {
+        for &code in &[SIO_UDP_CONNRESET, SIO_UDP_NETRESET] {
+            let mut enable = TRUE;
+            let mut returned = 0;
+            self.check(unsafe {
+                WSAIoctl(
+                    self.socket,
+                    code,
+                    &mut enable as *mut _ as *mut _,
+                    mem::size_of_val(&enable) as DWORD,
+                    ptr::null_mut(),
+                    0,
+                    &mut returned,
+                    ptr::null_mut(),
+                    None,
+                )
+            })?;
+        }
+        Ok(())
+    }
+    /// Limits how long a TCP socket retransmits, including SYNs while connecting, after
+    /// which the connection fails. Windows before 10 1607 only takes whole seconds.
+    pub(crate) fn set_max_retransmit_time(&self, ms: u32) -> Result<(), Error> {
+        let level = IPPROTO_TCP as c_int;
+        self.set_option(level, TCP_MAXRTMS, ms as DWORD)
+            .or_else(|_| self.set_option(level, TCP_MAXRT, ((ms + 999) / 1000) as DWORD))
+    }
+    pub(crate) fn set_nonblocking(&self) -> Result<(), Error> {
+        let mut nonblocking = 1;
+        self.check(unsafe { ioctlsocket(self.socket, FIONBIO, &mut nonblocking) })
+    }
+    pub(crate) fn bind(&self, addr: SocketAddr) -> Result<(), Error> {
+        let name = windows_sockaddr_inet(addr);
+        let len = sockaddr_inet_len(addr);
+        self.check(unsafe { bind(self.socket, &name as *const _ as *const SOCKADDR, len) })
+    }
+    /// Starts connecting a non-blocking socket. Returns Ok if the connection is
+    /// in progress, or has already been made.
+    pub(crate) fn start_connect(&self, addr: SocketAddr) -> Result<(), Error> {
+        let name = windows_sockaddr_inet(addr);
+        let len = sockaddr_inet_len(addr);
+        let ret = unsafe { connect(self.socket, &name as *const _ as *const SOCKADDR, len) };
+        if ret == SOCKET_ERROR {
+            match unsafe { WSAGetLastError() } {
+                WSAEWOULDBLOCK => Ok(()),
+                err => Err(self.error(err as u32)),
+            }
+        } else {
+            Ok(())
+        }
+    }
+    /// Waits up to timeout milliseconds for a connection to finish. Returns Ok(false)
+    /// if it's still in progress.
+    pub(crate) fn wait_connected(&self, timeout: u32) -> Result<bool, Error> {
+        // Winsock reports a connection in the write set, and a failed one in the except set
+        let mut write = self.fd_set();
+        let mut except = self.fd_set();
+        let timeout = to_timeval(timeout);
+        let ret = unsafe { select(0, ptr::null_mut(), &mut write, &mut except, &timeout) };
+        self.check(ret)?;
+        match self.take_error()? {
+            None => Ok(ret > 0),
+            Some(err) => Err(err),
+        }
+    }
+    /// Sends data on a connected socket.
+    pub(crate) fn send(&self, data: &[u8]) -> Result<(), Error> {
+        let ret = unsafe {
+            send(
+                self.socket,
+                data.as_ptr() as *const _,
+                data.len() as c_int,
+                0,
+            )
+        };
+        self.check(ret)
+    }
+    /// Sends data to the address, on a socket which isn't connected.
+    pub(crate) fn send_to(&self, data: &[u8], addr: SocketAddr) -> Result<(), Error> {
+        let name = windows_sockaddr_inet(addr);
+        let len = sockaddr_inet_len(addr);
+        let ret = unsafe {
+            sendto(
+                self.socket,
+                data.as_ptr() as *const _,
+                data.len() as c_int,
+                0,
+                &name as *const _ as *const SOCKADDR,
+                len,
+            )
+        };
+        self.check(ret)
+    }
+    /// Waits up to timeout milliseconds for data, or an error, to be received. Returns
+    /// Ok(false) if nothing was received.
+    pub(crate) fn wait_readable(&self, timeout: u32) -> Result<bool, Error> {
+        let mut read = self.fd_set();
+        let timeout = to_timeval(timeout);
+        let ret = unsafe { select(0, &mut read, ptr::null_mut(), ptr::null_mut(), &timeout) };
+        self.check(ret)?;
+        Ok(ret > 0)
+    }
+    /// Receives a datagram on a connected socket, and returns its length. A datagram
+    /// which doesn't fit in buf is truncated.
+    pub(crate) fn recv(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let ret = unsafe {
+            recv(
+                self.socket,
+                buf.as_mut_ptr() as *mut _,
+                buf.len() as c_int,
+                0,
+            )
+        };
+        if ret == SOCKET_ERROR && unsafe { WSAGetLastError() } == WSAEMSGSIZE {
+            return Ok(buf.len());
+        }
+        self.check(ret)?;
+        Ok(ret as usize)
+    }
+    /// Gets and clears the pending error on the socket, eg from a failed connection.
+    pub(crate) fn take_error(&self) -> Result<Option<Error>, Error> {
+        let mut err: DWORD = 0;
+        let mut len = mem::size_of::<DWORD>() as c_int;
+        self.check(unsafe {
+            getsockopt(
+                self.socket,
+                SOL_SOCKET,
+                SO_ERROR,
+                &mut err as *mut _ as *mut _,
+                &mut len,
+            )
+        })?;
+        Ok(match err {
+            0 => None,
+            err => Some(self.error(err)),
+        })
+    }
+    fn error(&self, err: u32) -> Error {
+        match (self.ty, err) {
+            // A TCP reset, the equivalent of an ICMP port unreachable
+            (SOCK_STREAM, WSAECONNREFUSED) => Error::PortUnreachable,
+            // How a UDP socket reports ICMP port unreachable and time exceeded errors,
+            // after set_udp_error_reports
+            (SOCK_DGRAM, WSAECONNRESET) => Error::PortUnreachable,
+            (SOCK_DGRAM, WSAENETRESET) if self.v6 => Error::HopLimitExceeded,
+            (SOCK_DGRAM, WSAENETRESET) => Error::TtlExpired,
+            _ if self.v6 => Error::from_winerror6(err),
+            _ => Error::from_winerror(err),
+        }
+    }
+    fn check(&self, ret: c_int) -> Result<(), Error> {
+        if ret == SOCKET_ERROR {
+            Err(self.error(unsafe { WSAGetLastError() } as u32))
+        } else {
+            Ok(())
+        }
+    }
+    fn set_option<T>(&self, level: c_int, name: c_int, value: T) -> Result<(), Error> {
+        let len = mem::size_of::<T>() as c_int;
+        self.check(unsafe {
+            setsockopt(
+                self.socket,
+                level,
+                name,
+                &value as *const _ as *const _,
+                len,
+            )
+        })
+    }
+    fn fd_set(&self) -> fd_set {
+        let mut set = fd_set {
+            fd_count: 1,
+            fd_array: [0; FD_SETSIZE],
+        };
+        set.fd_array[0] = self.socket;
+        set
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { closesocket(self.socket) };
+    }
+}
+
+/// Gets the socket addresses of a source and destination, with the destination port.
+pub(crate) fn socket_addrs(pair: IpPair, port: u16) -> (SocketAddr, SocketAddr) {
+    match pair {
+        IpPair::V4 { src, dst } => (
+            SocketAddr::new(src.into(), 0),
+            SocketAddr::new(dst.into(), port),
+        ),
+        IpPair::V6 { src, dst } => (
+            SocketAddr::new(src.into(), 0),
+            SocketAddr::new(dst.into(), port),
+        ),
+        IpPair::V6Scoped { src, dst } => (
+            SocketAddrV6::new(*src.ip(), 0, src.flowinfo(), src.scope_id()).into(),
+            SocketAddrV6::new(*dst.ip(), port, dst.flowinfo(), dst.scope_id()).into(),
+        ),
+    }
+}
+
+// Winsock must be started before use. It's never cleaned up, as other code in the
+// process may be using it.
+fn startup() {
+    static STARTUP: Once = Once::new();
+    STARTUP.call_once(|| unsafe {
+        let mut data: WSADATA = mem::zeroed();
+        WSAStartup(MAKEWORD(2, 2), &mut data);
+    });
+}
+
+// Converts a timeout in milliseconds for select.
+fn to_timeval(timeout: u32) -> timeval {
+    timeval {
+        tv_sec: (timeout / 1000) as _,
+        tv_usec: (timeout % 1000 * 1000) as _,
+    }
+}
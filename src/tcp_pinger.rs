@@ -0,0 +1,173 @@
+use winapi::shared::ws2def::{IPPROTO_TCP, SOCK_STREAM};
+
+#[cfg(feature = "async")]
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Instant,
+};
+
+#[cfg(feature = "async")]
+use crate::probe::ThreadFuture;
+use crate::{
+    socket::{socket_addrs, Socket},
+    Error, IpPair,
+};
+
+/// A pinger which measures how long it takes to open a TCP connection, rather than
+/// sending ICMP requests. This is useful for hosts behind a firewall which drops ICMP.
+///
+/// A send connects to the destination's port, and returns the handshake's round trip time
+/// in milliseconds if the port is open (SYN-ACK). A closed port (RST) is reported as
+/// [`Error::PortUnreachable`], and a filtered port (no response) as [`Error::Timeout`].
+/// The connection is reset as soon as it's made, and no data is sent.
+///
+/// Windows keeps retrying a connection which is refused until it gives up on it, so each
+/// attempt gives up after 250 ms, and a closed port is reported about then. If the port
+/// doesn't respond, another attempt is made, waiting twice as long, until the timeout. Each
+/// attempt is a new connection, so a filtered port may be sent several SYNs.
+#[derive(Copy, Clone, Debug)]
+pub struct TcpPinger {
+    port: u16,
+    ttl: u8,
+    timeout: u32,
+}
+
+// How long the first connection attempt may take, in milliseconds
+const FIRST_ATTEMPT_LIMIT: u32 = 250;
+
+/// A future for the result of a [`TcpPinger`] send. Each one runs on its own thread,
+/// so this is meant for a modest number of connections at once.
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct TcpPingFuture {
+    inner: ThreadFuture<Result<u32, Error>>,
+}
+
+impl TcpPinger {
+    /// Creates a TcpPinger which connects to the given port, eg 80 or 443.
+    pub fn new(port: u16) -> Self {
+        Self {
+            port,
+            ttl: 255,
+            timeout: 2000,
+        }
+    }
+    /// Sets the port for future requests.
+    pub fn set_port(&mut self, port: u16) {
+        self.port = port;
+    }
+    /// Gets the current port.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+    /// Sets the IP TTL for future requests.
+    pub fn set_ttl(&mut self, ttl: u8) {
+        self.ttl = ttl;
+    }
+    /// Gets the current IP TTL value.
+    pub fn ttl(&self) -> u8 {
+        self.ttl
+    }
+    /// Sets the timeout, in milliseconds, for future requests.
+    pub fn set_timeout(&mut self, timeout: u32) {
+        self.timeout = timeout;
+    }
+    /// Gets the current timeout in milliseconds.
+    pub fn timeout(&self) -> u32 {
+        self.timeout
+    }
+    /// Connects to the destination address. Supports both v4 and v6. On success, returns
+    /// the round trip time in milliseconds.
+    pub fn send(&self, dst: IpAddr) -> Result<u32, Error> {
+        self.connect(None, SocketAddr::new(dst, self.port))
+    }
+    /// Connects from the source address to the destination address. Supports both v4 and v6.
+    /// On success, returns the round trip time in milliseconds.
+    pub fn send_from(&self, src_dst_pair: IpPair) -> Result<u32, Error> {
+        let (src, dst) = socket_addrs(src_dst_pair, self.port);
+        self.connect(Some(src), dst)
+    }
+    /// Connects to the destination address on another thread, and returns a future
+    /// for the round trip time.
+    #[cfg(feature = "async")]
+    pub fn send_async(&self, dst: IpAddr) -> TcpPingFuture {
+        let dst = SocketAddr::new(dst, self.port);
+        TcpPingFuture::spawn(*self, None, dst)
+    }
+    /// Connects from the source address to the destination address on another thread,
+    /// and returns a future for the round trip time.
+    #[cfg(feature = "async")]
+    pub fn send_from_async(&self, src_dst_pair: IpPair) -> TcpPingFuture {
+        let (src, dst) = socket_addrs(src_dst_pair, self.port);
+        TcpPingFuture::spawn(*self, Some(src), dst)
+    }
+    fn connect(&self, src: Option<SocketAddr>, dst: SocketAddr) -> Result<u32, Error> {
+        let start = Instant::now();
+        let mut limit = FIRST_ATTEMPT_LIMIT;
+        loop {
+            let elapsed = start.elapsed().as_millis().min(u32::MAX as u128) as u32;
+            let remaining = self.timeout.saturating_sub(elapsed);
+            if remaining == 0 {
+                return Err(Error::Timeout);
+            }
+            match self.attempt(src, dst, limit.min(remaining), remaining) {
+                Err(Error::Timeout) => limit = limit.saturating_mul(2),
+                res => return res,
+            }
+        }
+    }
+    // Makes one connection attempt, which Windows gives up on after limit milliseconds
+    fn attempt(
+        &self,
+        src: Option<SocketAddr>,
+        dst: SocketAddr,
+        limit: u32,
+        timeout: u32,
+    ) -> Result<u32, Error> {
+        let socket = Socket::new(dst, SOCK_STREAM, IPPROTO_TCP as _)?;
+        socket.set_ttl(self.ttl)?;
+        socket.set_reset_on_close()?;
+        socket.set_max_retransmit_time(limit)?;
+        socket.set_nonblocking()?;
+        if let Some(src) = src {
+            socket.bind(src)?;
+        }
+        let start = Instant::now();
+        socket.start_connect(dst)?;
+        if socket.wait_connected(timeout)? {
+            Ok(start.elapsed().as_millis() as u32)
+        } else {
+            Err(Error::Timeout)
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl TcpPingFuture {
+    fn spawn(pinger: TcpPinger, src: Option<SocketAddr>, dst: SocketAddr) -> Self {
+        Self {
+            inner: ThreadFuture::spawn(move || pinger.connect(src, dst)),
+        }
+    }
+    /// Checks whether the request has completed, so polling or waiting won't block.
+    pub fn is_ready(&self) -> bool {
+        self.inner.is_ready()
+    }
+    /// Blocks the current thread until the request completes.
+    pub fn wait(self) -> Result<u32, Error> {
+        self.inner.wait()
+    }
+}
+
+#[cfg(feature = "async")]
+impl Future for TcpPingFuture {
+    type Output = Result<u32, Error>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.inner).poll(cx)
+    }
+}
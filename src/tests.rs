@@ -1,5 +1,5 @@
 use crate::*;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddrV6};
 
 #[cfg(feature = "async")]
 use futures::{
@@ -14,17 +14,56 @@ pub(crate) const LO6: Ipv6Addr = Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1);
 
 pub(crate) fn get_v4_pinger() -> Pinger {
     match Pinger::new() {
-        Ok(ret) | Err(CreateError::NoV6(ret)) => ret,
+        Ok(ret) | Err(CreateError::NoV6(ret, _)) => ret,
         _ => panic!("Could not create v4 pinger"),
     }
 }
 pub(crate) fn get_v6_pinger() -> Pinger {
     match Pinger::new() {
-        Ok(ret) | Err(CreateError::NoV4(ret)) => ret,
+        Ok(ret) | Err(CreateError::NoV4(ret, _)) => ret,
         _ => panic!("Could not create v6 pinger"),
     }
 }
 
+#[test]
+fn available_families() {
+    let pinger = get_v4_pinger();
+    assert!(pinger.available_families().v4);
+    // Opening an open handle does nothing
+    assert_eq!(Ok(()), pinger.open_v4());
+    let clone = pinger.clone();
+    let _ = clone.open_v6();
+    assert_eq!(clone.available_families(), pinger.available_families());
+}
+#[test]
+fn lazy_pinger() {
+    let pinger = Pinger::new_lazy();
+    let health = pinger.health();
+    assert!(!health.v4.open);
+    assert_eq!(0, health.v4.opens);
+    assert_eq!(None, health.v4.last_error);
+
+    let mut buf = Buffer::new();
+    assert!(pinger.clone().send4(LO4, &mut buf).is_ok());
+    let health = pinger.health();
+    assert!(health.v4.open);
+    assert_eq!(1, health.v4.opens);
+    assert_eq!(0, health.v4.reopens);
+    assert!(!health.v6.open);
+}
+#[test]
+fn create_error() {
+    let err = CreateError::None(Error::Other(5), Error::Other(10047));
+    assert_eq!(Some(Error::Other(5)), err.v4_error());
+    assert_eq!(Some(Error::Other(10047)), err.v6_error());
+    assert!(err
+        .to_string()
+        .starts_with("Failed to create ICMP V4 and V6 handles: "));
+    assert!(err.into_pinger().is_none());
+    let err = CreateError::NoV6(get_v4_pinger(), Error::Other(10047));
+    assert_eq!(None, err.v4_error());
+    assert!(err.into_pinger().is_some());
+}
 #[test]
 fn send4() {
     let mut buf = Buffer::new();
@@ -98,6 +137,108 @@ fn send6_from() {
     assert_eq!(buf.reply_data(), &buf.request_data[..]);
     assert_eq!(buf.responding_ip(), Some(IpAddr::V6(LO6)));
 }
+#[test]
+fn send6_scoped() {
+    let mut buf = Buffer::new();
+    for x in 0..=255 {
+        buf.request_data.push(x)
+    }
+    let pinger = get_v6_pinger();
+    let dst = parse_ipv6_scoped("::1%0").unwrap();
+    let res = pinger.send6_scoped(dst, &mut buf);
+    assert!(res.is_ok());
+    assert_eq!(buf.reply_data(), &buf.request_data[..]);
+    assert_eq!(buf.responding_ip(), Some(IpAddr::V6(LO6)));
+}
+#[test]
+fn send6_scoped_from() {
+    let mut buf = Buffer::new();
+    for x in 0..=255 {
+        buf.request_data.push(x)
+    }
+    let mut pinger = get_v6_pinger();
+    let lo = SocketAddrV6::new(LO6, 0, 0, 0);
+    let res = pinger.send_from(IpPair::V6Scoped { src: lo, dst: lo }, &mut buf);
+    assert!(res.is_ok());
+    assert_eq!(buf.reply_data(), &buf.request_data[..]);
+    assert_eq!(buf.responding_ip(), Some(IpAddr::V6(LO6)));
+}
+#[test]
+fn replies() {
+    let mut buf = Buffer::new();
+    for x in 0..=255 {
+        buf.request_data.push(x)
+    }
+    buf.set_max_replies(4);
+    let pinger = get_v4_pinger();
+    assert!(pinger.send4(LO4, &mut buf).is_ok());
+    let replies: Vec<_> = buf.replies().collect();
+    assert_eq!(1, replies.len());
+    assert!(replies[0].result().is_ok());
+    assert_eq!(IpAddr::V4(LO4), replies[0].responder());
+    assert_eq!(replies[0].data(), &buf.request_data[..]);
+    assert!(replies[0].ttl().is_some());
+}
+#[test]
+fn replies_empty() {
+    let buf = Buffer::new();
+    assert_eq!(0, buf.replies().count());
+}
+#[test]
+fn send_collect() {
+    let mut buf = Buffer::new();
+    buf.set_max_replies(4);
+    let pinger = get_v4_pinger();
+    let res = pinger.send_collect(IpAddr::V4(LO4), &mut buf);
+    let responders = res.unwrap();
+    assert_eq!(1, responders.len());
+    assert_eq!(IpAddr::V4(LO4), responders[0].addr);
+    assert!(responders[0].ttl.is_some());
+}
+#[test]
+fn send_collect_timeout() {
+    let mut buf = Buffer::new();
+    buf.set_max_replies(4);
+    let pinger = get_v4_pinger();
+    let res = pinger.send_collect(IpAddr::V4(BOGON4), &mut buf);
+    assert_eq!(Err(Error::Timeout), res);
+    assert!(buf.responders().is_empty());
+}
+#[test]
+fn send6_scoped_collect() {
+    let mut buf = Buffer::new();
+    buf.set_max_replies(4);
+    let pinger = get_v6_pinger();
+    let res = pinger.send6_scoped_collect(SocketAddrV6::new(LO6, 0, 0, 0), &mut buf);
+    let responders = res.unwrap();
+    assert_eq!(1, responders.len());
+    assert_eq!(IpAddr::V6(LO6), responders[0].addr);
+}
+#[test]
+fn send4_retry() {
+    let mut buf = Buffer::new();
+    let mut pinger = get_v4_pinger();
+    pinger.set_timeout(200);
+    pinger.set_retry_policy(Some(RetryPolicy {
+        backoff: std::time::Duration::from_millis(10),
+        ..RetryPolicy::new(3)
+    }));
+    assert_eq!(Err(Error::Timeout), pinger.send4(BOGON4, &mut buf));
+    assert_eq!(3, buf.attempts());
+    assert!(pinger.send4(LO4, &mut buf).is_ok());
+    assert_eq!(1, buf.attempts());
+}
+#[test]
+fn send4_retry_permanent() {
+    let mut buf = Buffer::new();
+    let mut pinger = get_v4_pinger();
+    pinger.set_retry_policy(Some(RetryPolicy::new(3)));
+    assert_eq!(
+        Err(Error::NetUnreachable),
+        pinger.send4_from(LO4, BOGON4, &mut buf)
+    );
+    assert_eq!(1, buf.attempts());
+}
 
 #[cfg(feature = "async")]
 #[test]
@@ -147,6 +288,143 @@ fn async_send4_timeout() {
 }
 #[cfg(feature = "async")]
 #[test]
+fn async_wait() {
+    use std::time::Duration;
+    let pinger = AsyncPinger::new();
+    let res = pinger.send4(LO4, Buffer::with_data(vec![1, 2, 3])).wait();
+    assert!(res.result.is_ok());
+    assert_eq!(&[1, 2, 3], res.buffer.reply_data());
+
+    let mut fut = pinger.send4(LO4, Buffer::new());
+    let res = loop {
+        match fut.wait_timeout(Duration::from_millis(1)) {
+            Ok(res) => break res,
+            Err(pending) => fut = pending,
+        }
+    };
+    assert!(res.result.is_ok());
+}
+#[cfg(feature = "async")]
+#[test]
+fn async_wait_timeout() {
+    use std::time::Duration;
+    let mut pinger = AsyncPinger::new();
+    pinger.set_timeout(500);
+    let fut = pinger.send4(BOGON4, Buffer::new());
+    let fut = fut.wait_timeout(Duration::from_millis(50)).err().unwrap();
+    assert!(!fut.is_ready());
+    let res = fut.wait_timeout(Duration::from_secs(5)).ok().unwrap();
+    assert_eq!(Err(Error::Timeout), res.result);
+}
+#[cfg(feature = "async")]
+#[test]
+fn async_wait_all() {
+    let pinger = AsyncPinger::new();
+    let futures = (0..10).map(|_| pinger.send4(LO4, Buffer::new()));
+    let results = PingFuture::wait_all(futures);
+    assert_eq!(10, results.len());
+    assert!(results.iter().all(|res| res.result.is_ok()));
+}
+#[cfg(feature = "async")]
+#[test]
+fn async_callback() {
+    let pinger = AsyncPinger::new();
+    let (tx, rx) = std::sync::mpsc::channel();
+    for _ in 0..10 {
+        let tx = tx.clone();
+        pinger.send_with_callback(LO4.into(), Buffer::new(), move |res| {
+            tx.send(res.result).unwrap()
+        });
+    }
+    drop(tx);
+    let results: Vec<_> = rx.iter().collect();
+    assert_eq!(10, results.len());
+    assert!(results.iter().all(Result::is_ok));
+}
+#[cfg(feature = "async")]
+#[test]
+fn async_callback_dispatcher() {
+    let mut pinger = AsyncPinger::new();
+    pinger.set_timeout(200);
+    pinger.set_retry_policy(Some(RetryPolicy {
+        backoff: std::time::Duration::from_millis(10),
+        ..RetryPolicy::new(2)
+    }));
+    let (jobs_tx, jobs_rx) = std::sync::mpsc::channel::<Box<dyn FnOnce() + Send>>();
+    let jobs_tx = std::sync::Mutex::new(jobs_tx);
+    pinger.set_callback_dispatcher(Some(std::sync::Arc::new(move |job| {
+        jobs_tx.lock().unwrap().send(job).unwrap()
+    })));
+    let (tx, rx) = std::sync::mpsc::channel();
+    pinger.send_with_callback(BOGON4.into(), Buffer::new(), move |res| {
+        tx.send((res.result, res.buffer.attempts())).unwrap()
+    });
+    // The callback only runs once this thread runs it
+    jobs_rx.recv().unwrap()();
+    assert_eq!((Err(Error::Timeout), 2), rx.try_recv().unwrap());
+}
+#[cfg(feature = "async")]
+#[test]
+fn async_send_batch() {
+    let pinger = AsyncPinger::new();
+    let requests = (0..100).map(|i| {
+        let dst = if i % 2 == 0 { LO4 } else { BOGON4 };
+        let mut buf = Buffer::new();
+        buf.request_data = vec![i as u8; 8];
+        (IpAddr::V4(dst), buf)
+    });
+    let results = pinger.send_batch(requests).wait_all();
+    assert_eq!(100, results.len());
+    for (i, res) in results.iter().enumerate() {
+        assert_eq!(res.buffer.request_data, vec![i as u8; 8]);
+        assert_eq!(res.result.is_ok(), i % 2 == 0);
+    }
+}
+#[cfg(feature = "async")]
+#[test]
+fn async_send_batch_next() {
+    let pinger = AsyncPinger::new();
+    let requests = (0..10).map(|_| (IpAddr::V4(LO4), Buffer::new()));
+    let mut batch = pinger.send_batch(requests);
+    let mut seen = [false; 10];
+    futures::executor::block_on(async {
+        while let Some((index, res)) = batch.next().await {
+            assert!(res.result.is_ok());
+            seen[index] = true;
+        }
+    });
+    assert_eq!(0, batch.remaining());
+    assert!(seen.iter().all(|&seen| seen));
+}
+#[cfg(feature = "async")]
+#[test]
+fn async_send4_retry() {
+    let mut pinger = AsyncPinger::new();
+    pinger.set_timeout(200);
+    pinger.set_retry_policy(Some(RetryPolicy {
+        backoff: std::time::Duration::from_millis(10),
+        ..RetryPolicy::new(3)
+    }));
+
+    let mut pool = LocalPool::new();
+    let spawner = pool.spawner();
+
+    for _ in 0..10 {
+        let fut = FutureObj::new(Box::pin(pinger.send4(BOGON4, Buffer::new()).map(|res| {
+            assert_eq!(res.result, Err(Error::Timeout));
+            assert_eq!(res.buffer.attempts(), 3);
+        })));
+        spawner.spawn_obj(fut).unwrap();
+        let fut = FutureObj::new(Box::pin(pinger.send4(LO4, Buffer::new()).map(|res| {
+            assert!(res.result.is_ok());
+            assert_eq!(res.buffer.attempts(), 1);
+        })));
+        spawner.spawn_obj(fut).unwrap();
+    }
+    pool.run();
+}
+#[cfg(feature = "async")]
+#[test]
 fn async_send6() {
     let pinger = AsyncPinger::new();
 
@@ -241,6 +519,163 @@ fn async_send6_from() {
     pool.run();
 }
 
+fn time_exceeded_v4(payload: &[u8]) -> Vec<u8> {
+    let mut msg = vec![11, 0, 0, 0, 0, 0, 0, 0];
+    // Quoted IPv4 header: TOS 0x10, DF set, TTL 1, ICMP, 10.0.0.1 -> BOGON4
+    msg.extend_from_slice(&[0x45, 0x10, 0, 0, 0x12, 0x34, 0x40, 0, 1, 1, 0, 0]);
+    msg.extend_from_slice(&[10, 0, 0, 1]);
+    msg.extend_from_slice(&BOGON4.octets());
+    // Quoted echo request: id 0x0102, seq 0x0304
+    msg.extend_from_slice(&[8, 0, 0, 0, 1, 2, 3, 4]);
+    msg.extend_from_slice(payload);
+    msg
+}
+#[test]
+fn parse_icmp_error_v4() {
+    let request_data: Vec<u8> = (0..=63).collect();
+    let msg = time_exceeded_v4(&request_data[..32]);
+    let err = IcmpErrorMessage::parse_v4(&msg).unwrap();
+    assert_eq!((11, 0), (err.icmp_type(), err.code()));
+    let quoted = err.quoted();
+    match quoted.ip() {
+        IpHeader::V4(header) => {
+            assert_eq!(0x10, header.tos);
+            assert_eq!(0x1234, header.id);
+            assert!(header.df);
+            assert_eq!(1, header.ttl);
+        }
+        IpHeader::V6(_) => panic!("expected v4 header"),
+    }
+    let echo = quoted.echo().unwrap();
+    assert_eq!((0x0102, 0x0304), (echo.identifier, echo.sequence));
+    assert_eq!(&request_data[..32], quoted.payload());
+    assert!(quoted.matches(IpAddr::V4(BOGON4), &request_data));
+    assert!(!quoted.matches(IpAddr::V4(LO4), &request_data));
+    assert!(!quoted.matches(IpAddr::V4(BOGON4), &request_data[1..]));
+}
+#[test]
+fn parse_icmp_error_v6() {
+    let mut msg = vec![3, 0, 0, 0, 0, 0, 0, 0];
+    // Quoted IPv6 header: traffic class 0xb8, flow label 0x12345, ICMPv6, hop limit 1
+    msg.extend_from_slice(&[0x6b, 0x81, 0x23, 0x45, 0, 8, 58, 1]);
+    msg.extend_from_slice(&LO6.octets());
+    msg.extend_from_slice(&LO6.octets());
+    msg.extend_from_slice(&[128, 0, 0, 0, 0, 1, 0, 2]);
+    let err = IcmpErrorMessage::parse_v6(&msg).unwrap();
+    match err.quoted().ip() {
+        IpHeader::V6(header) => {
+            assert_eq!(0xb8, header.traffic_class);
+            assert_eq!(0x12345, header.flow_label);
+            assert_eq!(1, header.hop_limit);
+        }
+        IpHeader::V4(_) => panic!("expected v6 header"),
+    }
+    assert!(err.quoted().matches(IpAddr::V6(LO6), &[1, 2, 3]));
+}
+#[test]
+fn parse_icmp_extensions() {
+    let request_data: Vec<u8> = (0..128).collect();
+    let mut msg = time_exceeded_v4(&request_data[..100]);
+    // Quoted datagram is 20 + 8 + 100 = 128 bytes, or 32 words
+    msg[5] = 32;
+    // Extension header, version 2
+    msg.extend_from_slice(&[0x20, 0, 0, 0]);
+    // MPLS label stack: label 16001, exp 5, not bottom, TTL 1; label 299776, exp 0, bottom, TTL 1
+    msg.extend_from_slice(&[0, 12, 1, 1]);
+    msg.extend_from_slice(&((16001 << 12) | (5 << 9) | 1u32).to_be_bytes());
+    msg.extend_from_slice(&((299_776 << 12) | 0x100 | 1u32).to_be_bytes());
+    // Incoming interface: ifIndex 7, IPv4 192.0.2.1, name "ge-0/0/0", MTU 1500
+    msg.extend_from_slice(&[0, 32, 2, 0x0f]);
+    msg.extend_from_slice(&7u32.to_be_bytes());
+    msg.extend_from_slice(&[0, 1, 0, 0, 192, 0, 2, 1]);
+    msg.extend_from_slice(&[12, b'g', b'e', b'-', b'0', b'/', b'0', b'/', b'0', 0, 0, 0]);
+    msg.extend_from_slice(&1500u32.to_be_bytes());
+    let err = IcmpErrorMessage::parse_v4(&msg).unwrap();
+    assert!(err.quoted().matches(IpAddr::V4(BOGON4), &request_data));
+    assert_eq!(
+        vec![
+            IcmpExtension::Mpls(vec![
+                MplsLabel {
+                    label: 16001,
+                    exp: 5,
+                    s: false,
+                    ttl: 1
+                },
+                MplsLabel {
+                    label: 299_776,
+                    exp: 0,
+                    s: true,
+                    ttl: 1
+                },
+            ]),
+            IcmpExtension::Interface(InterfaceInfo {
+                role: InterfaceRole::Incoming,
+                if_index: Some(7),
+                addr: Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+                name: Some("ge-0/0/0"),
+                mtu: Some(1500),
+            }),
+        ],
+        err.extensions()
+    );
+}
+#[test]
+fn parse_icmp_extensions_non_compliant() {
+    let request_data: Vec<u8> = (0..100).collect();
+    // Length field left zero, extensions start 128 bytes into the quoted datagram
+    let mut msg = time_exceeded_v4(&request_data);
+    msg.extend_from_slice(&[0x20, 0, 0, 0, 0, 8, 1, 1]);
+    msg.extend_from_slice(&((16001 << 12) | 0x100 | 1u32).to_be_bytes());
+    let err = IcmpErrorMessage::parse_v4(&msg).unwrap();
+    assert_eq!(&request_data[..], err.quoted().payload());
+    assert_eq!(1, err.extensions().len());
+    // No extensions at all
+    let msg = time_exceeded_v4(&request_data);
+    assert!(IcmpErrorMessage::parse_v4(&msg)
+        .unwrap()
+        .extensions()
+        .is_empty());
+}
+#[test]
+fn parse_icmp_error_rejects_non_errors() {
+    let mut msg = time_exceeded_v4(&[]);
+    msg[0] = 0; // Echo reply
+    assert_eq!(None, IcmpErrorMessage::parse_v4(&msg));
+    assert_eq!(None, IcmpErrorMessage::parse_v4(&msg[..20]));
+    assert_eq!(None, QuotedDatagram::parse(&[0x45, 0, 0]));
+}
+
+#[test]
+fn error_v6_statuses() {
+    use winapi::um::ipexport::{IP_DEST_PROHIBITED, IP_HOP_LIMIT_EXCEEDED, IP_REQ_TIMED_OUT};
+    assert_eq!(
+        Error::ProtocolUnreachable,
+        Error::from_iperror(IP_DEST_PROHIBITED)
+    );
+    assert_eq!(
+        Error::AdminProhibited,
+        Error::from_iperror6(IP_DEST_PROHIBITED)
+    );
+    assert_eq!(
+        Error::TtlExpired,
+        Error::from_iperror(IP_HOP_LIMIT_EXCEEDED)
+    );
+    assert_eq!(
+        Error::HopLimitExceeded,
+        Error::from_iperror6(IP_HOP_LIMIT_EXCEEDED)
+    );
+    assert_eq!(Error::Timeout, Error::from_iperror6(IP_REQ_TIMED_OUT));
+}
+#[test]
+fn error_icmp_type_code() {
+    assert_eq!(Some((11, 0)), Error::TtlExpired.icmpv4_type_code());
+    assert_eq!(None, Error::TtlExpired.icmpv6_type_code());
+    assert_eq!(Some((3, 0)), Error::HopLimitExceeded.icmpv6_type_code());
+    assert_eq!(Some((1, 1)), Error::AdminProhibited.icmpv6_type_code());
+    assert_eq!(Some((3, 3)), Error::PortUnreachable.icmpv4_type_code());
+    assert_eq!(Some((1, 4)), Error::PortUnreachable.icmpv6_type_code());
+    assert_eq!(None, Error::Timeout.icmpv4_type_code());
+}
 #[test]
 fn error_win_display() {
     let e = Error::Other(0);
@@ -253,3 +688,457 @@ fn error_ip_display() {
     let s = format!("{}", e);
     assert!(s.ends_with("Buffer too small."));
 }
+#[test]
+fn error_unknown_display() {
+    let e = Error::Other(0xdead);
+    assert_eq!("Other error (57005)", format!("{}", e));
+}
+#[test]
+fn error_codes() {
+    assert_eq!("timeout", Error::Timeout.code());
+    assert_eq!("admin_prohibited", Error::AdminProhibited.code());
+    assert_eq!("hop_limit_exceeded", Error::HopLimitExceeded.code());
+    assert_eq!("other", Error::Other(0).code());
+}
+#[test]
+fn error_localized_display() {
+    // Assumes an English UI language, like the tests above
+    let s = format!("{}", Error::Other(0).localized());
+    assert!(s.ends_with("The operation completed successfully."));
+    let s = format!("{}", Error::Other(11001).localized());
+    assert!(s.ends_with("Buffer too small."));
+    assert_eq!(Error::Timeout, Error::Timeout.localized().error());
+}
+#[test]
+fn error_into_io_error() {
+    use std::io;
+    let e: io::Error = Error::Timeout.into();
+    assert_eq!(io::ErrorKind::TimedOut, e.kind());
+    assert_eq!(Some(&Error::Timeout), e.get_ref().unwrap().downcast_ref());
+    assert_eq!(Error::Timeout, Error::from(e));
+    let e: io::Error = Error::NetUnreachable.into();
+    assert_eq!(io::Error::from_raw_os_error(10051).kind(), e.kind());
+    let e: io::Error = Error::Other(5).into();
+    assert_eq!(io::ErrorKind::PermissionDenied, e.kind());
+}
+#[test]
+fn error_from_io_error() {
+    use std::io;
+    assert_eq!(
+        Error::HostUnreachable,
+        io::Error::from_raw_os_error(10065).into()
+    );
+    assert_eq!(Error::Other(5), io::Error::from_raw_os_error(5).into());
+    assert_eq!(
+        Error::Timeout,
+        io::Error::from(io::ErrorKind::TimedOut).into()
+    );
+}
+#[test]
+fn error_is_transient() {
+    assert!(Error::Timeout.is_transient());
+    assert!(Error::NoResources.is_transient());
+    assert!(Error::Other(14).is_transient());
+    assert!(!Error::NetUnreachable.is_transient());
+    assert!(!Error::AdminProhibited.is_transient());
+    assert!(!Error::Other(5).is_transient());
+}
+#[test]
+fn retry_policy() {
+    use std::time::Duration;
+    let mut policy = RetryPolicy {
+        max_attempts: 3,
+        backoff: Duration::from_millis(100),
+        max_backoff: Duration::from_millis(300),
+        jitter: 0.0,
+        retry_on: None,
+    };
+    assert!(policy.should_retry(Error::Timeout, 1));
+    assert!(policy.should_retry(Error::Timeout, 2));
+    assert!(!policy.should_retry(Error::Timeout, 3));
+    assert!(!policy.should_retry(Error::HostUnreachable, 1));
+    assert_eq!(Duration::from_millis(100), policy.delay(1));
+    assert_eq!(Duration::from_millis(200), policy.delay(2));
+    assert_eq!(Duration::from_millis(300), policy.delay(3));
+    assert_eq!(Duration::from_millis(300), policy.delay(100));
+
+    policy.retry_on = Some(vec![Error::HostUnreachable]);
+    assert!(policy.should_retry(Error::HostUnreachable, 1));
+    assert!(!policy.should_retry(Error::Timeout, 1));
+
+    policy.jitter = 0.5;
+    for attempts in 1..10 {
+        let delay = policy.delay(attempts);
+        assert!(delay >= Duration::from_millis(50));
+        assert!(delay <= Duration::from_millis(450));
+    }
+}
+#[cfg(feature = "serde")]
+#[test]
+fn serde_error() {
+    assert_eq!(
+        r#""timeout""#,
+        serde_json::to_string(&Error::Timeout).unwrap()
+    );
+    assert_eq!(
+        r#"{"other":5}"#,
+        serde_json::to_string(&Error::Other(5)).unwrap()
+    );
+    for err in [Error::AdminProhibited, Error::NoRoute, Error::Other(11050)] {
+        let json = serde_json::to_string(&err).unwrap();
+        assert_eq!(err, serde_json::from_str(&json).unwrap());
+    }
+    assert!(serde_json::from_str::<Error>(r#""not_an_error""#).is_err());
+    let res: Result<u32, Error> = Err(Error::HostUnreachable);
+    assert_eq!(
+        r#"{"Err":"host_unreachable"}"#,
+        serde_json::to_string(&res).unwrap()
+    );
+}
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip() {
+    let pair = IpPair::V6Scoped {
+        src: SocketAddrV6::new(LO6, 0, 0, 3),
+        dst: SocketAddrV6::new("fe80::1".parse().unwrap(), 0, 0, 3),
+    };
+    let json = serde_json::to_string(&pair).unwrap();
+    assert_eq!(pair, serde_json::from_str(&json).unwrap());
+
+    let responder = Responder {
+        addr: IpAddr::V4(LO4),
+        rtt: 1,
+        ttl: Some(128),
+    };
+    let json = serde_json::to_string(&responder).unwrap();
+    assert_eq!(responder, serde_json::from_str(&json).unwrap());
+
+    let policy = RetryPolicy {
+        retry_on: Some(vec![Error::Timeout]),
+        ..RetryPolicy::default()
+    };
+    let json = serde_json::to_string(&policy).unwrap();
+    assert_eq!(policy, serde_json::from_str(&json).unwrap());
+}
+#[cfg(feature = "serde")]
+#[test]
+fn serde_buffer() {
+    let mut buf = Buffer::with_data(vec![1, 2, 3]);
+    let pinger = get_v4_pinger();
+    pinger.send4(LO4, &mut buf).unwrap();
+    let json: serde_json::Value = serde_json::to_value(&buf).unwrap();
+    assert_eq!(1, json["attempts"]);
+    let reply = &json["replies"][0];
+    assert!(reply["result"]["Ok"].is_u64());
+    assert_eq!("127.0.0.1", reply["responder"]);
+    assert_eq!(serde_json::json!([1, 2, 3]), reply["data"]);
+}
+#[test]
+fn rtt_estimator() {
+    use crate::rtt::Estimator;
+    let rtt = Estimator::new(AdaptiveTimeout {
+        initial: 1000,
+        min: 10,
+        max: 3000,
+    });
+    let dst = IpAddr::V4(BOGON4);
+    assert_eq!(1000, rtt.timeout(dst));
+    // A timeout backs off before there are any samples
+    rtt.observe(dst, Err(Error::Timeout));
+    assert_eq!(2000, rtt.timeout(dst));
+    // The first sample replaces the backed off timeout
+    rtt.observe(dst, Ok(100));
+    let e = rtt.estimate(dst).unwrap();
+    assert_eq!((100.0, 50.0, 300, 1), (e.srtt, e.rttvar, e.rto, e.samples));
+    rtt.observe(dst, Ok(100));
+    let e = rtt.estimate(dst).unwrap();
+    assert_eq!((100.0, 37.5, 250, 2), (e.srtt, e.rttvar, e.rto, e.samples));
+    // Other errors are ignored
+    rtt.observe(dst, Err(Error::HostUnreachable));
+    assert_eq!(250, rtt.timeout(dst));
+    for _ in 0..4 {
+        rtt.observe(dst, Err(Error::Timeout));
+    }
+    assert_eq!(3000, rtt.timeout(dst));
+    // The minimum applies to fast hosts
+    let lo = IpAddr::V4(LO4);
+    rtt.observe(lo, Ok(0));
+    assert_eq!(10, rtt.timeout(lo));
+}
+#[test]
+fn rtt_estimator_prune() {
+    use crate::rtt::Estimator;
+    let rtt = Estimator::new(AdaptiveTimeout {
+        initial: 1,
+        min: 1,
+        max: 1,
+    });
+    for i in 0..1024u32 {
+        rtt.observe(IpAddr::V4((0x0a00_0000 + i).into()), Ok(1));
+    }
+    // Estimates not observed within 64 times the longest timeout are removed
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    rtt.observe(IpAddr::V4(LO4), Ok(1));
+    assert_eq!(None, rtt.estimate(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0))));
+    assert!(rtt.estimate(IpAddr::V4(LO4)).is_some());
+}
+#[test]
+fn adaptive_timeout() {
+    let mut buf = Buffer::new();
+    let mut pinger = get_v4_pinger();
+    pinger.set_adaptive_timeout(Some(AdaptiveTimeout::default()));
+    assert_eq!(None, pinger.rtt_estimate(IpAddr::V4(LO4)));
+    pinger.send4(LO4, &mut buf).unwrap();
+    let e = pinger.rtt_estimate(IpAddr::V4(LO4)).unwrap();
+    assert_eq!(1, e.samples);
+    assert_eq!(100, e.rto);
+}
+#[test]
+fn rate_limiter() {
+    use crate::rate::Limiter;
+    use std::time::{Duration, Instant};
+    let limiter = Limiter::new(RateLimit {
+        global: Some(Rate {
+            per_second: 100.0,
+            burst: 2,
+        }),
+        per_destination: None,
+        per_subnet: Some(SubnetRate {
+            v4_prefix: 24,
+            v6_prefix: 64,
+            rate: Rate::per_second(10.0),
+        }),
+    });
+    let start = Instant::now();
+    let a = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    let b = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+    let c = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1));
+    // A burst of 2 is allowed globally
+    assert_eq!(start, limiter.reserve(a, start));
+    assert_eq!(start, limiter.reserve(c, start));
+    // b is in the same /24 as a, so waits for the subnet
+    let at = limiter.reserve(b, start);
+    assert!(at >= start + Duration::from_millis(99));
+    assert!(at <= start + Duration::from_millis(101));
+    // c's subnet is limited separately
+    let at = limiter.reserve(c, start);
+    assert!(at >= start + Duration::from_millis(99));
+}
+#[test]
+#[should_panic(expected = "rate must be positive")]
+fn rate_rejects_nan() {
+    Rate::per_second(f64::NAN);
+}
+#[test]
+fn rate_limit() {
+    use std::time::{Duration, Instant};
+    let mut buf = Buffer::new();
+    let mut pinger = get_v4_pinger();
+    pinger.set_rate_limit(Some(RateLimit {
+        per_destination: Some(Rate::per_second(20.0)),
+        ..RateLimit::default()
+    }));
+    let start = Instant::now();
+    for _ in 0..5 {
+        pinger.send4(LO4, &mut buf).unwrap();
+    }
+    assert!(start.elapsed() >= Duration::from_millis(195));
+}
+#[test]
+fn buffer_pool() {
+    let pool = BufferPool::with_max_idle(vec![7; 16], 2);
+    pool.preallocate(1);
+    let mut buf = pool.get();
+    assert_eq!(buf.request_data, vec![7; 16]);
+    let pinger = get_v4_pinger();
+    pinger.send4(LO4, &mut buf).unwrap();
+    assert_eq!(buf.reply_data(), &[7; 16][..]);
+    buf.request_data = vec![1, 2, 3];
+    pool.put(buf);
+    let buf = pool.get();
+    assert_eq!(buf.request_data, vec![7; 16]);
+    assert_eq!(buf.reply_data(), &[] as &[u8]);
+    assert_eq!(buf.attempts(), 0);
+    let others = (pool.get(), pool.get());
+    pool.put(buf);
+    pool.put(others.0);
+    pool.put(others.1);
+    assert_eq!(
+        pool.stats(),
+        PoolStats {
+            allocated: 3,
+            reused: 2,
+            returned: 4,
+            dropped: 1,
+            idle: 2,
+        }
+    );
+}
+#[cfg(feature = "async")]
+#[test]
+fn buffer_pool_async() {
+    let pool = BufferPool::new(vec![1; 8]);
+    let pinger = AsyncPinger::new();
+    for _ in 0..3 {
+        let res = pinger.send4(LO4, pool.get()).wait();
+        assert!(pool.put_result(res).is_ok());
+    }
+    let stats = pool.stats();
+    assert_eq!((stats.allocated, stats.reused, stats.idle), (1, 2, 1));
+}
+#[test]
+fn send_borrowed() {
+    const PAYLOAD: &[u8] = b"borrowed payload";
+    let pinger = get_v4_pinger();
+    #[repr(align(16))]
+    struct Area([u8; reply_area_size(PAYLOAD.len()) + 1]);
+    let mut area = Area([0; reply_area_size(PAYLOAD.len()) + 1]);
+    // Misaligned on purpose, as the alignment is fixed up. This wastes the most bytes.
+    let reply = pinger.send_borrowed(LO4.into(), PAYLOAD, &mut area.0[1..]);
+    assert!(reply.result().is_ok());
+    assert_eq!(reply.attempts(), 1);
+    assert_eq!(reply.reply_data(), PAYLOAD);
+    assert_eq!(reply.responding_ip(), Some(IpAddr::V4(LO4)));
+
+    let mut small = [0; 16];
+    let reply = pinger.send_borrowed(LO4.into(), PAYLOAD, &mut small);
+    assert_eq!(reply.result(), Err(Error::BufferTooSmall));
+    assert_eq!(reply.attempts(), 0);
+    assert_eq!(reply.replies().count(), 0);
+}
+#[test]
+fn timestamp_payload() {
+    let pinger = get_v4_pinger();
+    let mut payload = TimestampPayload::with_session(7);
+    payload.set_data_len(32);
+    let mut buf = Buffer::new();
+    assert_eq!(payload.write(&mut buf), 0);
+    assert_eq!(buf.request_data.len(), 32);
+    let os_rtt = pinger.send4(LO4, &mut buf).unwrap();
+    let timing = payload.read(&buf).unwrap();
+    assert_eq!(timing.seq, 0);
+    assert_eq!(timing.os_rtt, Some(os_rtt));
+    assert!(timing.overhead.is_some());
+    assert!(!timing.stale);
+
+    // The reply to the first request is stale once the next one is written
+    assert_eq!(payload.write(&mut buf), 1);
+    assert!(payload.read(&buf).unwrap().stale);
+
+    // Replies from other sessions are ignored
+    let other = TimestampPayload::with_session(8);
+    assert_eq!(other.read(&buf), None);
+}
+#[test]
+fn error_from_icmp_type_code() {
+    assert_eq!(
+        Error::from_icmpv4_type_code(3, 3),
+        Some(Error::PortUnreachable)
+    );
+    assert_eq!(Error::from_icmpv4_type_code(11, 0), Some(Error::TtlExpired));
+    assert_eq!(
+        Error::from_icmpv4_type_code(3, 13),
+        Some(Error::DestUnreachable)
+    );
+    assert_eq!(Error::from_icmpv4_type_code(0, 0), None);
+    assert_eq!(
+        Error::from_icmpv6_type_code(1, 4),
+        Some(Error::PortUnreachable)
+    );
+    assert_eq!(
+        Error::from_icmpv6_type_code(3, 0),
+        Some(Error::HopLimitExceeded)
+    );
+    assert_eq!(Error::from_icmpv6_type_code(128, 0), None);
+    for err in [Error::NetUnreachable, Error::SourceQuench, Error::BadRoute].iter() {
+        let (icmp_type, code) = err.icmpv4_type_code().unwrap();
+        assert_eq!(Error::from_icmpv4_type_code(icmp_type, code), Some(*err));
+    }
+}
+#[test]
+fn tcp_pinger() {
+    let listener = std::net::TcpListener::bind((LO4, 0)).unwrap();
+    let mut pinger = TcpPinger::new(listener.local_addr().unwrap().port());
+    assert!(pinger.send(LO4.into()).is_ok());
+    let pair = IpPair::V4 { src: LO4, dst: LO4 };
+    assert!(pinger.send_from(pair).is_ok());
+    #[cfg(feature = "async")]
+    assert!(futures::executor::block_on(pinger.send_async(LO4.into())).is_ok());
+
+    // A refused connection is reported well within the default timeout
+    drop(listener);
+    let start = std::time::Instant::now();
+    assert_eq!(pinger.send(LO4.into()), Err(Error::PortUnreachable));
+    assert!(start.elapsed().as_millis() < 1000);
+
+    pinger.set_timeout(200);
+    assert_eq!(pinger.send(BOGON4.into()), Err(Error::Timeout));
+}
+#[test]
+fn udp_prober() {
+    let server = std::net::UdpSocket::bind((LO4, 0)).unwrap();
+    let mut prober = UdpProber::new(server.local_addr().unwrap().port());
+    prober.set_payload(b"probe".to_vec());
+    let echo = std::thread::spawn(move || {
+        let mut buf = [0; 16];
+        let (len, from) = server.recv_from(&mut buf).unwrap();
+        server.send_to(&buf[..len], from).unwrap();
+    });
+    assert!(prober.send(LO4.into()).is_ok());
+    echo.join().unwrap();
+
+    // Nothing is listening on the port now
+    assert_eq!(prober.send(LO4.into()), Err(Error::PortUnreachable));
+    // which shows the target was reached, so the probe names it and times the reply
+    let res = prober.probe(LO4.into(), &ProbeOptions::default());
+    assert_eq!(res.result, Err(Error::PortUnreachable));
+    assert!(res.rtt.is_some());
+    assert_eq!(res.responder, Some(IpAddr::V4(LO4)));
+
+    prober.set_timeout(200);
+    assert_eq!(prober.send(BOGON4.into()), Err(Error::Timeout));
+}
+#[test]
+fn probe_trait() {
+    let listener = std::net::TcpListener::bind((LO4, 0)).unwrap();
+    let options = ProbeOptions {
+        port: Some(listener.local_addr().unwrap().port()),
+        ..ProbeOptions::default()
+    };
+    let probes: Vec<Box<dyn Probe>> = vec![
+        Box::new(get_v4_pinger()),
+        Box::new(TcpPinger::new(1)),
+        #[cfg(feature = "async")]
+        Box::new(AsyncPinger::new()),
+    ];
+    for probe in &probes {
+        let res = probe.probe(LO4.into(), &options);
+        assert!(res.result.is_ok());
+        assert!(res.rtt.is_some());
+        assert_eq!(res.responder, Some(IpAddr::V4(LO4)));
+    }
+
+    // The source must be the same IP version as the target
+    let options = ProbeOptions {
+        source: Some(LO6.into()),
+        ..options
+    };
+    let res = probes[0].probe(LO4.into(), &options);
+    assert_eq!(res.result, Err(Error::BadRequest));
+    assert_eq!(res.rtt, None);
+}
+#[cfg(feature = "async")]
+#[test]
+fn async_probe_trait() {
+    let probes: Vec<Box<dyn AsyncProbe>> =
+        vec![Box::new(AsyncPinger::new()), Box::new(get_v4_pinger())];
+    let options = ProbeOptions::default();
+    let futures: Vec<_> = probes
+        .iter()
+        .map(|probe| probe.probe_async(LO4.into(), &options))
+        .collect();
+    for res in futures::executor::block_on(futures::future::join_all(futures)) {
+        assert!(res.result.is_ok());
+        assert_eq!(res.responder, Some(IpAddr::V4(LO4)));
+    }
+}
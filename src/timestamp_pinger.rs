@@ -0,0 +1,185 @@
+use winapi::shared::ws2def::{IPPROTO_ICMP, SOCK_RAW};
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::atomic::{AtomicU16, Ordering},
+    time::Instant,
+};
+
+use crate::{socket::Socket, Error, IcmpErrorMessage, IcmpTimestamp, TimestampReply};
+
+// ICMP Timestamp (type 13) requests can't be sent with IcmpSendEcho, so these are
+// sent on a raw socket, which sees every ICMP message the host receives.
+const TIMESTAMP: u8 = 13;
+// Large enough for any reply or error on an ordinary link
+const PACKET_LEN: usize = 1500;
+
+static SEQ: AtomicU16 = AtomicU16::new(0);
+
+/// A pinger which sends ICMP Timestamp requests (RFC 792), to estimate the one way delays
+/// and clock offset to a host. This is IPv4 only, as ICMPv6 has no Timestamp message.
+///
+/// The Windows ICMP API can only send echo requests, so these are sent on a raw socket.
+/// This needs administrator rights, and a send fails with `Error::Other(10013)`
+/// (WSAEACCES) without them. An ICMP error for the request, eg from a router when the TTL
+/// runs out, is returned as the matching [`Error`], such as [`Error::TtlExpired`]. Many
+/// hosts don't answer Timestamp requests at all, which is reported as [`Error::Timeout`].
+#[derive(Copy, Clone, Debug)]
+pub struct TimestampPinger {
+    ttl: u8,
+    timeout: u32,
+}
+
+impl Default for TimestampPinger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TimestampPinger {
+    /// Creates a TimestampPinger with the same defaults as a [`Pinger`](crate::Pinger).
+    pub fn new() -> Self {
+        Self {
+            ttl: 255,
+            timeout: 2000,
+        }
+    }
+    /// Sets the IP TTL for future requests.
+    pub fn set_ttl(&mut self, ttl: u8) {
+        self.ttl = ttl;
+    }
+    /// Gets the current IP TTL value.
+    pub fn ttl(&self) -> u8 {
+        self.ttl
+    }
+    /// Sets the timeout, in milliseconds, for future requests.
+    pub fn set_timeout(&mut self, timeout: u32) {
+        self.timeout = timeout;
+    }
+    /// Gets the current timeout in milliseconds.
+    pub fn timeout(&self) -> u32 {
+        self.timeout
+    }
+    /// Sends a Timestamp request to the destination address, and returns the reply's
+    /// timestamps with the delays estimated from them.
+    pub fn send(&self, dst: Ipv4Addr) -> Result<TimestampReply, Error> {
+        self.try_send(Ipv4Addr::UNSPECIFIED, dst)
+    }
+    /// Sends a Timestamp request from the source address to the destination address, and
+    /// returns the reply's timestamps with the delays estimated from them.
+    pub fn send_from(&self, src: Ipv4Addr, dst: Ipv4Addr) -> Result<TimestampReply, Error> {
+        self.try_send(src, dst)
+    }
+    fn try_send(&self, src: Ipv4Addr, dst: Ipv4Addr) -> Result<TimestampReply, Error> {
+        let dst_addr = SocketAddr::new(dst.into(), 0);
+        let socket = Socket::new(dst_addr, SOCK_RAW, IPPROTO_ICMP as _)?;
+        socket.set_ttl(self.ttl)?;
+        socket.set_nonblocking()?;
+        // Windows only delivers to a raw socket once it's bound
+        socket.bind(SocketAddr::new(src.into(), 0))?;
+
+        let id = std::process::id() as u16;
+        let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+        let request = IcmpTimestamp::request(id, seq);
+        let start = Instant::now();
+        socket.send_to(&request.encode_request(), dst_addr)?;
+
+        let mut packet = [0; PACKET_LEN];
+        loop {
+            let elapsed = start.elapsed().as_millis().min(u32::MAX as u128) as u32;
+            let remaining = self.timeout.saturating_sub(elapsed);
+            if remaining == 0 || !socket.wait_readable(remaining)? {
+                return Err(Error::Timeout);
+            }
+            let len = socket.recv(&mut packet)?;
+            match check_packet(&packet[..len], dst, id, seq) {
+                Some(Ok(reply)) => {
+                    let received = IcmpTimestamp::now();
+                    return Ok(TimestampReply {
+                        timestamp: reply,
+                        received,
+                        rtt: start.elapsed().as_millis() as u32,
+                        delays: reply.delays(received),
+                    });
+                }
+                Some(Err(err)) => return Err(err),
+                // Another process's ICMP traffic
+                None => {}
+            }
+        }
+    }
+}
+
+// Checks whether a datagram received on the raw socket is the reply to the request with
+// id and seq, or an error about it. Errors from routers are matched by the request they quote.
+fn check_packet(
+    packet: &[u8],
+    dst: Ipv4Addr,
+    id: u16,
+    seq: u16,
+) -> Option<Result<IcmpTimestamp, Error>> {
+    let (from, icmp) = split_ipv4(packet)?;
+    if let Some(reply) = IcmpTimestamp::parse_reply(icmp) {
+        if from == dst && reply.id == id && reply.seq == seq {
+            return Some(Ok(reply));
+        }
+    } else if let Some(err) = IcmpErrorMessage::parse_v4(icmp) {
+        let quoted = err.quoted();
+        let ours = quoted.echo().map_or(false, |header| {
+            header.icmp_type == TIMESTAMP && header.identifier == id && header.sequence == seq
+        });
+        if ours && quoted.ip().dst() == IpAddr::V4(dst) {
+            return Some(Err(err.error()));
+        }
+    }
+    None
+}
+
+// Splits a datagram received on a raw IPv4 socket into its source and ICMP message.
+fn split_ipv4(packet: &[u8]) -> Option<(Ipv4Addr, &[u8])> {
+    let header_len = (*packet.first()? & 0x0f) as usize * 4;
+    if header_len < 20 {
+        return None;
+    }
+    let src = packet.get(12..16)?;
+    let src = Ipv4Addr::new(src[0], src[1], src[2], src[3]);
+    Some((src, packet.get(header_len..)?))
+}
+
+#[test]
+fn check_timestamp_packets() {
+    const DST: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+    const ROUTER: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 1);
+    // A 20 byte IPv4 header, with only the fields which are read
+    fn ipv4(src: Ipv4Addr, dst: Ipv4Addr, ttl: u8, body: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0, 0, ttl, 1, 0, 0];
+        packet.extend_from_slice(&src.octets());
+        packet.extend_from_slice(&dst.octets());
+        packet.extend_from_slice(body);
+        packet
+    }
+    let request = IcmpTimestamp::request(7, 9);
+    let reply = IcmpTimestamp {
+        receive: request.originate,
+        transmit: request.originate,
+        ..request
+    };
+    let packet = ipv4(DST, Ipv4Addr::LOCALHOST, 64, &reply.encode_reply());
+    assert_eq!(check_packet(&packet, DST, 7, 9), Some(Ok(reply)));
+    // Another request's reply, or the same reply from elsewhere
+    assert_eq!(check_packet(&packet, DST, 7, 10), None);
+    let packet = ipv4(ROUTER, Ipv4Addr::LOCALHOST, 64, &reply.encode_reply());
+    assert_eq!(check_packet(&packet, DST, 7, 9), None);
+
+    // A time exceeded from a router, quoting the request
+    let quoted = ipv4(Ipv4Addr::LOCALHOST, DST, 1, &request.encode_request());
+    let mut error = vec![11, 0, 0, 0, 0, 0, 0, 0];
+    error.extend_from_slice(&quoted);
+    let packet = ipv4(ROUTER, Ipv4Addr::LOCALHOST, 64, &error);
+    assert_eq!(
+        check_packet(&packet, DST, 7, 9),
+        Some(Err(Error::TtlExpired))
+    );
+    assert_eq!(check_packet(&packet, DST, 8, 9), None);
+    assert_eq!(check_packet(&[0x40; 8], DST, 7, 9), None);
+}
@@ -0,0 +1,122 @@
+use winapi::shared::ws2def::{IPPROTO_UDP, SOCK_DGRAM};
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Instant,
+};
+
+use crate::{
+    socket::{socket_addrs, Socket},
+    Error, IpPair,
+};
+
+/// A prober which sends UDP datagrams, like a classic Unix traceroute, rather than
+/// ICMP echo requests. This is useful for paths which treat ICMP echo differently.
+///
+/// A send sends the payload to the destination's port, and waits for a response. If a
+/// datagram comes back, its round trip time is returned in milliseconds. The usual response
+/// to a probe of an unused port is an ICMP port unreachable, which is returned as
+/// [`Error::PortUnreachable`] and shows that the destination was reached. No response at
+/// all is [`Error::Timeout`].
+///
+/// Windows has no equivalent of `IP_RECVERR`, so the ICMP error itself can't be read
+/// without a raw socket, which needs elevated rights. Instead, Windows reports port unreachable
+/// and time exceeded errors as socket errors, which are mapped to [`Error::PortUnreachable`]
+/// and [`Error::TtlExpired`] (or [`Error::HopLimitExceeded`] for IPv6). Unlike an ICMP echo,
+/// the address of the router which sent a time exceeded error is not known.
+///
+/// Probing with [`Probe`](crate::Probe) also returns the round trip time of an ICMP error,
+/// and names the destination as the responder for a port unreachable, as only the destination
+/// reports its own port as unreachable.
+#[derive(Clone, Debug)]
+pub struct UdpProber {
+    port: u16,
+    ttl: u8,
+    timeout: u32,
+    payload: Vec<u8>,
+}
+
+impl UdpProber {
+    /// Creates a UdpProber which sends to the given port, with an empty payload. Traceroute
+    /// conventionally uses ports from 33434, which are unlikely to be in use.
+    pub fn new(port: u16) -> Self {
+        Self {
+            port,
+            ttl: 255,
+            timeout: 2000,
+            payload: Vec::new(),
+        }
+    }
+    /// Sets the port for future requests.
+    pub fn set_port(&mut self, port: u16) {
+        self.port = port;
+    }
+    /// Gets the current port.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+    /// Sets the IP TTL for future requests.
+    pub fn set_ttl(&mut self, ttl: u8) {
+        self.ttl = ttl;
+    }
+    /// Gets the current IP TTL value.
+    pub fn ttl(&self) -> u8 {
+        self.ttl
+    }
+    /// Sets the timeout, in milliseconds, for future requests.
+    pub fn set_timeout(&mut self, timeout: u32) {
+        self.timeout = timeout;
+    }
+    /// Gets the current timeout in milliseconds.
+    pub fn timeout(&self) -> u32 {
+        self.timeout
+    }
+    /// Sets the data sent in each datagram.
+    pub fn set_payload(&mut self, payload: Vec<u8>) {
+        self.payload = payload;
+    }
+    /// Gets the data sent in each datagram.
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+    /// Sends a datagram to the destination address. Supports both v4 and v6. On success,
+    /// returns the round trip time in milliseconds.
+    pub fn send(&self, dst: IpAddr) -> Result<u32, Error> {
+        self.try_send(None, SocketAddr::new(dst, self.port))
+            .map_err(|(err, _)| err)
+    }
+    /// Sends a datagram from the source address to the destination address. Supports both
+    /// v4 and v6. On success, returns the round trip time in milliseconds.
+    pub fn send_from(&self, src_dst_pair: IpPair) -> Result<u32, Error> {
+        let (src, dst) = socket_addrs(src_dst_pair, self.port);
+        self.try_send(Some(src), dst).map_err(|(err, _)| err)
+    }
+    /// Sends a datagram, and returns the round trip time, or the error with the round trip
+    /// time if the error was a response, eg an ICMP port unreachable.
+    pub(crate) fn try_send(
+        &self,
+        src: Option<SocketAddr>,
+        dst: SocketAddr,
+    ) -> Result<u32, (Error, Option<u32>)> {
+        let no_rtt = |err| (err, None);
+        let socket = Socket::new(dst, SOCK_DGRAM, IPPROTO_UDP as _).map_err(no_rtt)?;
+        socket.set_ttl(self.ttl).map_err(no_rtt)?;
+        socket.set_udp_error_reports().map_err(no_rtt)?;
+        socket.set_nonblocking().map_err(no_rtt)?;
+        if let Some(src) = src {
+            socket.bind(src).map_err(no_rtt)?;
+        }
+        // Connecting a UDP socket sends nothing, but means only datagrams from dst are received
+        socket.start_connect(dst).map_err(no_rtt)?;
+        let start = Instant::now();
+        socket.send(&self.payload).map_err(no_rtt)?;
+        // An ICMP error makes the socket readable, and is returned by recv
+        let rtt = |err| (err, Some(start.elapsed().as_millis() as u32));
+        if !socket.wait_readable(self.timeout).map_err(rtt)? {
+            return Err((Error::Timeout, None));
+        }
+        // Any response will do, so it isn't kept
+        socket.recv(&mut [0; 1]).map_err(rtt)?;
+        Ok(start.elapsed().as_millis() as u32)
+    }
+}
@@ -1,6 +1,16 @@
-use winapi::shared::in6addr::in6_addr;
+use winapi::shared::{
+    ifdef::NET_LUID,
+    in6addr::in6_addr,
+    netioapi::{if_nametoindex, ConvertInterfaceAliasToLuid, ConvertInterfaceLuidToIndex},
+    winerror::NO_ERROR,
+    ws2def::{AF_INET, AF_INET6, SOCKADDR_IN},
+    ws2ipdef::{SOCKADDR_IN6, SOCKADDR_INET},
+};
 
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::{
+    ffi::CString,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
+};
 /// Converts a Rust IPv4 to a Windows IPv4
 pub(crate) fn windows_ipv4(ip: Ipv4Addr) -> u32 {
     u32::from_ne_bytes(ip.octets())
@@ -15,6 +25,44 @@ pub(crate) fn windows_ipv6(ip: Ipv6Addr) -> in6_addr {
     // so might as well just transmute it.
     unsafe { std::mem::transmute(ip) }
 }
+/// Converts a Rust IPv6 socket address to a Windows SOCKADDR_IN6, keeping
+/// the scope ID and flow info. The port is ignored.
+pub(crate) fn windows_sockaddr6(addr: SocketAddrV6) -> SOCKADDR_IN6 {
+    let mut ret = SOCKADDR_IN6 {
+        sin6_family: AF_INET6 as _,
+        // Copied unchanged, as std does when converting to and from a sockaddr_in6
+        sin6_flowinfo: addr.flowinfo(),
+        sin6_addr: windows_ipv6(*addr.ip()),
+        ..Default::default()
+    };
+    unsafe { *ret.u.sin6_scope_id_mut() = addr.scope_id() };
+    ret
+}
+/// Converts a Rust socket address to a Windows SOCKADDR_INET, keeping the port.
+pub(crate) fn windows_sockaddr_inet(addr: SocketAddr) -> SOCKADDR_INET {
+    let mut ret = SOCKADDR_INET::default();
+    match addr {
+        SocketAddr::V4(addr) => {
+            let v4 = unsafe { ret.Ipv4_mut() };
+            v4.sin_family = AF_INET as _;
+            v4.sin_port = addr.port().to_be();
+            unsafe { *v4.sin_addr.S_un.S_addr_mut() = windows_ipv4(*addr.ip()) };
+        }
+        SocketAddr::V6(addr) => {
+            let v6 = unsafe { ret.Ipv6_mut() };
+            *v6 = windows_sockaddr6(addr);
+            v6.sin6_port = addr.port().to_be();
+        }
+    }
+    ret
+}
+/// Gets the length of a SOCKADDR_INET created from addr.
+pub(crate) fn sockaddr_inet_len(addr: SocketAddr) -> i32 {
+    match addr {
+        SocketAddr::V4(_) => std::mem::size_of::<SOCKADDR_IN>() as i32,
+        SocketAddr::V6(_) => std::mem::size_of::<SOCKADDR_IN6>() as i32,
+    }
+}
 /// Converts a Windows IPv6 to a Rust IPv6
 #[allow(clippy::many_single_char_names)]
 pub(crate) fn rust_ipv6(ip: [u16; 8]) -> Ipv6Addr {
@@ -30,6 +78,51 @@ pub(crate) fn rust_ipv6(ip: [u16; 8]) -> Ipv6Addr {
         u16::from_be(h),
     )
 }
+/// Parses an IPv6 address with an optional zone suffix, such as `fe80::1%12`,
+/// `fe80::1%Ethernet` or `[fe80::1%12]`. The zone may be a numeric scope ID or
+/// the name or alias of a network interface. The returned address has a port
+/// and flow info of 0.
+///
+/// Returns None if the address cannot be parsed or the interface does not exist.
+pub fn parse_ipv6_scoped(s: &str) -> Option<SocketAddrV6> {
+    let s = s.trim();
+    let s = if s.starts_with('[') && s.ends_with(']') {
+        &s[1..s.len() - 1]
+    } else {
+        s
+    };
+    let (ip, zone) = match s.find('%') {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+    let ip = ip.parse::<Ipv6Addr>().ok()?;
+    let scope_id = match zone {
+        None => 0,
+        Some(zone) => zone.parse::<u32>().ok().or_else(|| interface_index(zone))?,
+    };
+    Some(SocketAddrV6::new(ip, 0, 0, scope_id))
+}
+/// Looks up an interface index by alias (eg "Ethernet") or by name (eg "ethernet_32768").
+fn interface_index(name: &str) -> Option<u32> {
+    if name.is_empty() {
+        return None;
+    }
+    let wide: Vec<u16> = name.encode_utf16().chain(Some(0)).collect();
+    let mut luid = NET_LUID::default();
+    let mut index = 0;
+    unsafe {
+        if ConvertInterfaceAliasToLuid(wide.as_ptr(), &mut luid) == NO_ERROR
+            && ConvertInterfaceLuidToIndex(&luid, &mut index) == NO_ERROR
+        {
+            return Some(index);
+        }
+    }
+    let name = CString::new(name).ok()?;
+    match unsafe { if_nametoindex(name.as_ptr()) } {
+        0 => None,
+        index => Some(index),
+    }
+}
 
 #[test]
 #[allow(clippy::many_single_char_names)]
@@ -44,3 +137,36 @@ fn ip_conv_is_correct() {
     });
     assert_eq!(Ipv6Addr::LOCALHOST, rust_ipv6(localhost6_segments_be));
 }
+
+#[test]
+fn sockaddr6_conv_keeps_scope_and_flowinfo() {
+    let addr = SocketAddrV6::new("fe80::1".parse().unwrap(), 0, 0x12345, 7);
+    let sockaddr = windows_sockaddr6(addr);
+    assert_eq!(AF_INET6 as u16, sockaddr.sin6_family);
+    assert_eq!(0x12345, sockaddr.sin6_flowinfo);
+    assert_eq!(7, unsafe { *sockaddr.u.sin6_scope_id() });
+    assert_eq!(
+        *addr.ip(),
+        rust_ipv6(*unsafe { sockaddr.sin6_addr.u.Word() })
+    );
+}
+
+#[test]
+fn parse_ipv6_scoped_numeric() {
+    let lo = Ipv6Addr::LOCALHOST;
+    let ll = "fe80::1".parse::<Ipv6Addr>().unwrap();
+    assert_eq!(
+        Some(SocketAddrV6::new(lo, 0, 0, 0)),
+        parse_ipv6_scoped("::1")
+    );
+    assert_eq!(
+        Some(SocketAddrV6::new(ll, 0, 0, 12)),
+        parse_ipv6_scoped("fe80::1%12")
+    );
+    assert_eq!(
+        Some(SocketAddrV6::new(ll, 0, 0, 12)),
+        parse_ipv6_scoped("[fe80::1%12]")
+    );
+    assert_eq!(None, parse_ipv6_scoped("fe80::1%"));
+    assert_eq!(None, parse_ipv6_scoped("127.0.0.1"));
+}